@@ -1,7 +1,8 @@
 //! Contains all of the structs related to change requests, responses and more.
 
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::Display, sync::Arc};
 
+use futures::future::{join_all, BoxFuture};
 use lazy_async_promise::BoxedSendError;
 use tokio::sync::{
     mpsc,
@@ -50,6 +51,10 @@ where
     //}
 }
 
+/// Function applied to a value by a [`ChangeType::Modify`].
+pub type Modifier<Value> = Arc<dyn Fn(&mut Value) + Send + Sync>;
+
+#[derive(Clone)]
 pub enum ChangeType<Key, Value>
 where
     Key: KeyBounds,
@@ -57,10 +62,34 @@ where
 {
     Insert(Value),
     InsertMany(Vec<Value>),
+    /// Inserts only the values whose key doesn't exist yet, e.g. to seed
+    /// defaults. The storage resolves which values were actually inserted.
+    InsertIfAbsentMany(Vec<Value>),
     Update(Value),
     UpdateMany(Vec<Value>),
     Delete(Key),
     DeleteMany(Vec<Key>),
+    /// Moves the value with `key` so that it sits after `after` and before
+    /// `before`. The storage resolves this into the actual updated value.
+    Move {
+        key: Key,
+        before: Option<Key>,
+        after: Option<Key>,
+    },
+    /// Applies `f` to the current value with `key` inside of the storage, so
+    /// that e.g. incrementing a counter doesn't race with other communicators.
+    /// Since `f` can't be serialized this only works for storages in the same
+    /// process.
+    Modify { key: Key, f: Modifier<Value> },
+    /// Moves the value with the key `old` to the key `new`, e.g. once the
+    /// server assigned the final key to a value inserted with a temporary one.
+    /// `set_key` gives the value its new key. Communicators holding `old` are
+    /// sent a delete of it and an insert of the value under `new`.
+    Rekey {
+        old: Key,
+        new: Key,
+        set_key: Modifier<Value>,
+    },
 }
 
 impl<Key, Value> ChangeType<Key, Value>
@@ -71,6 +100,7 @@ where
     pub fn is_empty(&self) -> bool {
         match self {
             ChangeType::InsertMany(vals) => vals.is_empty(),
+            ChangeType::InsertIfAbsentMany(vals) => vals.is_empty(),
             ChangeType::UpdateMany(vals) => vals.is_empty(),
             ChangeType::DeleteMany(vals) => vals.is_empty(),
             _ => false,
@@ -86,10 +116,14 @@ impl<Key: KeyBounds, Value: ValueBounds<Key>> Display for ChangeType<Key, Value>
             match self {
                 Self::Insert(_) => String::from("Insert"),
                 Self::InsertMany(vals) => format!("InsertMany({})", vals.len()),
+                Self::InsertIfAbsentMany(vals) => format!("InsertIfAbsentMany({})", vals.len()),
                 Self::Update(_) => String::from("Update"),
                 Self::UpdateMany(vals) => format!("UpdateMany({})", vals.len()),
                 Self::Delete(_) => String::from("Delete"),
                 Self::DeleteMany(vals) => format!("DeleteMany({})", vals.len()),
+                Self::Move { .. } => String::from("Move"),
+                Self::Modify { .. } => String::from("Modify"),
+                Self::Rekey { .. } => String::from("Rekey"),
             }
         )
     }
@@ -97,6 +131,15 @@ impl<Key: KeyBounds, Value: ValueBounds<Key>> Display for ChangeType<Key, Value>
 
 pub enum ChangeResponse<Key: KeyBounds, Value: ValueBounds<Key>> {
     Ok(DataChange<Key, Value>),
+    /// Same as `Ok` but with the number of values that were actually affected,
+    /// see [`ChangeResult::Affected`].
+    Affected(DataChange<Key, Value>, usize),
+    /// The value that was moved from the key `old` by a [`ChangeType::Rekey`].
+    Rekeyed { old: Key, value: Value },
+    /// The keys that `cascaded` from the keys deleted by the `action`, which
+    /// the container then deletes together, see
+    /// [`Storage::on_delete_cascade`][crate::container::storage::Storage::on_delete_cascade].
+    Cascade { action: ChangeType<Key, Value>, cascaded: Vec<Key> },
     Err(ChangeError),
 }
 
@@ -106,24 +149,21 @@ where
     Value: ValueBounds<Key>,
 {
     pub fn empty_ok(change_type: ChangeType<Key, Value>) -> Self {
-        match change_type {
-            ChangeType::Insert(_) | ChangeType::InsertMany(_) => {
-                Self::Ok(DataChange::empty_insert())
-            }
-            ChangeType::Update(_) | ChangeType::UpdateMany(_) => {
-                Self::Ok(DataChange::empty_update())
-            }
-            ChangeType::Delete(_) | ChangeType::DeleteMany(_) => {
-                Self::Ok(DataChange::empty_delete())
-            }
-        }
+        Self::Ok(empty_data_change(&change_type))
     }
+    /// Changes that are resolved by the storage, like a [`ChangeType::Move`],
+    /// don't know their values here and are reported as empty.
     pub fn from_type_and_result(
         action_type: ChangeType<Key, Value>,
         action_result: ChangeResult,
     ) -> Self {
+        let data_change = || {
+            DataChange::try_from(action_type)
+                .unwrap_or_else(|action_type| empty_data_change(&action_type))
+        };
         match action_result {
-            ChangeResult::Success => Self::Ok(action_type.into()),
+            ChangeResult::Success => Self::Ok(data_change()),
+            ChangeResult::Affected(count) => Self::Affected(data_change(), count),
             ChangeResult::Error(err) => Self::Err(err),
         }
     }
@@ -137,6 +177,17 @@ where
     fn from(value: ChangeResponse<Key, Value>) -> Self {
         match value {
             ChangeResponse::Ok(data) => (Some(data), ChangeResult::Success),
+            ChangeResponse::Affected(data, count) => (Some(data), ChangeResult::Affected(count)),
+            // NOTE: the container broadcasts the delete of `old` itself, see
+            // `ResolvingAction::resolve`
+            ChangeResponse::Rekeyed { value, .. } => {
+                (Some(DataChange::Insert(vec![value])), ChangeResult::Success)
+            }
+            // NOTE: the container deletes the keys before anything is sent,
+            // see `ResolvingAction::resolve`
+            ChangeResponse::Cascade { .. } => {
+                (None, ChangeResult::Error(ChangeError::DefaultError))
+            }
             ChangeResponse::Err(err) => (None, ChangeResult::Error(err)),
         }
     }
@@ -145,6 +196,9 @@ where
 #[derive(Clone, Debug)]
 pub enum ChangeResult {
     Success,
+    /// Success of a change for which the storage knows how many values were
+    /// actually affected, e.g. how many of the deleted keys existed.
+    Affected(usize),
     Error(ChangeError),
 }
 
@@ -154,6 +208,18 @@ pub enum ChangeError {
     DatabaseError(String),
     ChannelSendError(String),
     ChannelReciveError(RecvError),
+    Unsupported(String),
+}
+
+impl ChangeResult {
+    /// Both [`Success`][ChangeResult::Success] and
+    /// [`Affected`][ChangeResult::Affected] are `Ok`.
+    pub fn into_result(self) -> Result<(), ChangeError> {
+        match self {
+            Self::Success | Self::Affected(_) => Ok(()),
+            Self::Error(err) => Err(err),
+        }
+    }
 }
 
 impl ChangeError {
@@ -170,6 +236,79 @@ impl Display for ChangeError {
 
 impl Error for ChangeError {}
 
+/// So that storages can use `?` on their backend's errors.
+impl From<std::io::Error> for ChangeError {
+    fn from(value: std::io::Error) -> Self {
+        Self::DatabaseError(value.to_string())
+    }
+}
+
+impl From<Box<dyn Error>> for ChangeError {
+    fn from(value: Box<dyn Error>) -> Self {
+        Self::DatabaseError(value.to_string())
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for ChangeError {
+    fn from(value: Box<dyn Error + Send + Sync>) -> Self {
+        Self::DatabaseError(value.to_string())
+    }
+}
+
+/// Future returned by the change functions of a communicator, including the
+/// ones produced by its `_action` closures.
+pub type ChangeFuture = BoxFuture<'static, Result<ChangeResult, BoxedSendError>>;
+
+/// Collects the futures of many changes, e.g. generated with an `_action`
+/// closure in a loop, so that their results can be awaited together.
+#[derive(Default)]
+pub struct ActionBatch {
+    futures: Vec<ChangeFuture>,
+}
+
+impl ActionBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, future: ChangeFuture) -> &mut Self {
+        self.futures.push(future);
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.futures.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+    /// Awaits every change, the results are in the order the changes were
+    /// added. A change that couldn't be sent is a [`ChangeError::ChannelSendError`].
+    pub async fn join(self) -> Vec<ChangeResult> {
+        join_all(self.futures)
+            .await
+            .into_iter()
+            .map(|result| {
+                result.unwrap_or_else(|err| {
+                    ChangeResult::Error(ChangeError::ChannelSendError(format!("{err}")))
+                })
+            })
+            .collect()
+    }
+}
+
+impl Extend<ChangeFuture> for ActionBatch {
+    fn extend<T: IntoIterator<Item = ChangeFuture>>(&mut self, iter: T) {
+        self.futures.extend(iter);
+    }
+}
+
+impl FromIterator<ChangeFuture> for ActionBatch {
+    fn from_iter<T: IntoIterator<Item = ChangeFuture>>(iter: T) -> Self {
+        Self {
+            futures: iter.into_iter().collect(),
+        }
+    }
+}
+
 impl From<Result<ChangeResult, RecvError>> for ChangeResult {
     fn from(value: Result<ChangeResult, RecvError>) -> Self {
         match value {
@@ -182,13 +321,13 @@ impl From<Result<ChangeResult, RecvError>> for ChangeResult {
 impl From<ChangeResult> for Result<(), BoxedSendError> {
     fn from(value: ChangeResult) -> Self {
         match value {
-            ChangeResult::Success => Ok(()),
+            ChangeResult::Success | ChangeResult::Affected(_) => Ok(()),
             ChangeResult::Error(err) => Err(err.into()),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum DataChange<Key, Value>
 where
     Key: KeyBounds,
@@ -251,21 +390,58 @@ where
     pub fn is_delete(&self) -> bool {
         matches!(self, Self::Delete(_))
     }
+
+    /// Turns every value of an insert or update into another value type, the
+    /// keys of a delete are kept as they are.
+    pub fn map_values<W: ValueBounds<Key>>(self, f: impl Fn(Value) -> W) -> DataChange<Key, W> {
+        match self {
+            Self::Insert(values) => DataChange::Insert(values.into_iter().map(f).collect()),
+            Self::Update(values) => DataChange::Update(values.into_iter().map(f).collect()),
+            Self::Delete(keys) => DataChange::Delete(keys),
+        }
+    }
 }
 
-impl<Key, Value> From<ChangeType<Key, Value>> for DataChange<Key, Value>
+/// Fails with the change itself for a [`ChangeType::Move`],
+/// [`ChangeType::Modify`], [`ChangeType::Rekey`] or
+/// [`ChangeType::InsertIfAbsentMany`], since those can only be turned into a
+/// data change by the storage.
+impl<Key, Value> TryFrom<ChangeType<Key, Value>> for DataChange<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
-    fn from(value: ChangeType<Key, Value>) -> Self {
-        match value {
+    type Error = ChangeType<Key, Value>;
+    fn try_from(value: ChangeType<Key, Value>) -> Result<Self, Self::Error> {
+        Ok(match value {
             ChangeType::Insert(val) => Self::Insert(vec![val]),
             ChangeType::InsertMany(vals) => Self::Insert(vals),
             ChangeType::Update(val) => Self::Update(vec![val]),
             ChangeType::UpdateMany(vals) => Self::Update(vals),
             ChangeType::Delete(key) => Self::Delete(vec![key]),
             ChangeType::DeleteMany(keys) => Self::Delete(keys),
+            change @ (ChangeType::Move { .. }
+            | ChangeType::Modify { .. }
+            | ChangeType::InsertIfAbsentMany(_)
+            | ChangeType::Rekey { .. }) => return Err(change),
+        })
+    }
+}
+
+fn empty_data_change<Key, Value>(change_type: &ChangeType<Key, Value>) -> DataChange<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    match change_type {
+        ChangeType::Insert(_) | ChangeType::InsertMany(_) | ChangeType::InsertIfAbsentMany(_) => {
+            DataChange::empty_insert()
         }
+        ChangeType::Update(_)
+        | ChangeType::UpdateMany(_)
+        | ChangeType::Move { .. }
+        | ChangeType::Modify { .. }
+        | ChangeType::Rekey { .. } => DataChange::empty_update(),
+        ChangeType::Delete(_) | ChangeType::DeleteMany(_) => DataChange::empty_delete(),
     }
 }
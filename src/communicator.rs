@@ -1,23 +1,66 @@
 pub mod data;
 
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    mem::Discriminant,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex, OnceLock,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
 
-use data::Data;
-use futures::future::BoxFuture;
+use data::{Data, DataSnapshot, DataStats, Move, SortId};
+use futures::{future::BoxFuture, stream, task::noop_waker_ref, FutureExt, Stream};
 use itertools::Itertools;
-use lazy_async_promise::BoxedSendError;
-use tokio::sync::mpsc;
+use lazy_async_promise::{BoxedSendError, DirectCacheAccess, ImmediateValuePromise};
+use tokio::sync::{
+    mpsc::{self, error::TryRecvError},
+    watch,
+};
+use tokio::time::Instant;
 use tracing::{debug, info, trace};
 use uuid::Uuid;
 
-use crate::{change::DataChange, query::FreshData};
+use crate::{
+    change::DataChange,
+    container::Signal,
+    query::FreshData,
+    utils::{DrainIf, PromiseUtilities},
+};
 
 use super::{
-    change::{Change, ChangeError, ChangeResult, ChangeType},
-    query::{DataQuery, QueryError, QueryResult, QueryType},
-    KeyBounds, ValueBounds,
+    change::{ActionBatch, Change, ChangeError, ChangeResult, ChangeType},
+    query::{DataQuery, Priority, QueryError, QueryProgress, QueryResult, QueryType},
+    KeyBounds, SetKey, ValueBounds,
 };
 
+type DeleteCallback<Key> = Box<dyn FnMut(&[Key]) + Send + 'static>;
+type DataAvailableCallback = Box<dyn Fn() + Send + Sync + 'static>;
+type EqualityFn<Value> = fn(&Value, &Value) -> bool;
+
+/// Query waiting for its delay to pass, see [`Communicator::query_debounced`].
+struct DebouncedQuery<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    query_type: QueryType<Key, Value>,
+    deadline: Instant,
+}
+
+/// Change that was sent with a callback, see [`Communicator::insert_then`].
+struct ResultCallback {
+    promise: ImmediateValuePromise<ChangeResult>,
+    callback: Box<dyn FnOnce(ChangeResult) + Send + 'static>,
+}
+
+/// How often [`Communicator::await_empty`] and the other functions of the
+/// communicator and container that wait for updates check for new ones.
+pub(crate) const AWAIT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
 /// The struct through which you view and change the data.
 ///
 /// #### View
@@ -72,6 +115,33 @@ where
     reciver: Reciver<Key, Value>,
     pub data: Data<Key, Value>,
     has_changed: bool,
+    /// Keys of all values that changed since [`set_viewed`][Communicator::set_viewed].
+    changed_keys: HashSet<Key>,
+    signals: Vec<Signal>,
+    total_in_storage: Option<usize>,
+    on_delete: Option<DeleteCallback<Key>>,
+    on_data_available: Option<DataAvailableCallback>,
+    /// Changes that were only applied locally, see
+    /// [`queue_offline_change`][Communicator::queue_offline_change]. Shared
+    /// with [`flush_offline`][Communicator::flush_offline] to put back the
+    /// changes it couldn't send.
+    offline_changes: Arc<Mutex<Vec<ChangeType<Key, Value>>>>,
+    result_callbacks: Mutex<Vec<ResultCallback>>,
+    /// Set by [`skip_equal_updates`][Communicator::skip_equal_updates].
+    equal_values: Option<EqualityFn<Value>>,
+    /// Created by the first call to [`watch_map`][Communicator::watch_map].
+    map_watch: OnceLock<watch::Sender<HashMap<Key, Value>>>,
+    /// See [`select`][Communicator::select].
+    selected: HashSet<Key>,
+    debounced_query: Mutex<Option<DebouncedQuery<Key, Value>>>,
+    /// Debounced query that was sent, polled during every
+    /// [`state_update`][Communicator::state_update].
+    running_debounced: Option<BoxFuture<'static, Result<QueryResult, BoxedSendError>>>,
+    /// See [`debounced_result`][Communicator::debounced_result].
+    debounced_result: Option<Result<QueryResult, BoxedSendError>>,
+    /// Streamed queries that were sent but didn't resolve yet, polled during
+    /// every [`state_update`][Communicator::state_update].
+    running_streamed: Mutex<Vec<BoxFuture<'static, ()>>>,
 }
 
 impl<Key, Value> Communicator<Key, Value>
@@ -80,32 +150,299 @@ where
     Value: ValueBounds<Key>,
 {
     #[must_use]
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         uuid: Uuid,
         change_sender: mpsc::Sender<Change<Key, Value>>,
         query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+        priority_query_sender: mpsc::Sender<DataQuery<Key, Value>>,
         change_data_reciver: mpsc::Receiver<DataChange<Key, Value>>,
+        grown_change_reciver: mpsc::UnboundedReceiver<mpsc::Receiver<DataChange<Key, Value>>>,
         fresh_data_reciver: mpsc::Receiver<FreshData<Key, Value>>,
+        signal_reciver: mpsc::UnboundedReceiver<Signal>,
+    ) -> Self {
+        let sender = Sender::new(change_sender, query_sender, priority_query_sender);
+        let reciver = Reciver::new(
+            change_data_reciver,
+            grown_change_reciver,
+            fresh_data_reciver,
+            signal_reciver,
+        );
+        Self::from_parts(uuid, sender, reciver)
+    }
+    /// Puts a communicator back together from the parts returned by
+    /// [`into_parts`][Communicator::into_parts]. The data starts out empty.
+    #[must_use]
+    pub fn from_parts(
+        uuid: Uuid,
+        sender: Sender<Key, Value>,
+        reciver: Reciver<Key, Value>,
     ) -> Self {
-        let sender = Sender::new(change_sender, query_sender);
-        let reciver = Reciver::new(change_data_reciver, fresh_data_reciver);
         Self {
             uuid,
             sender,
             reciver,
             data: Data::new(),
             has_changed: true,
+            changed_keys: HashSet::new(),
+            signals: vec![],
+            total_in_storage: None,
+            on_delete: None,
+            on_data_available: None,
+            offline_changes: Arc::default(),
+            result_callbacks: Mutex::new(vec![]),
+            equal_values: None,
+            map_watch: OnceLock::new(),
+            selected: HashSet::new(),
+            debounced_query: Mutex::new(None),
+            running_debounced: None,
+            debounced_result: None,
+            running_streamed: Mutex::new(vec![]),
         }
     }
-    /// Recives any new updates and then updates the internal data accordingly
+    /// Splits the communicator into its uuid and the halves through which it
+    /// talks to the container, e.g. to move them somewhere else and rebuild
+    /// the communicator there with [`from_parts`][Communicator::from_parts].
+    /// The local data and any callbacks are dropped.
+    pub fn into_parts(self) -> (Uuid, Sender<Key, Value>, Reciver<Key, Value>) {
+        (self.uuid, self.sender, self.reciver)
+    }
+    /// Recives any new updates and then updates the internal data accordingly.
+    /// The data is resorted only once after all updates were applied.
     pub fn state_update(&mut self) {
-        self.reciver.recive_new().into_iter().for_each(|action| {
-            match action {
-                RecievedAction::Change(update) => self.data.update_data(update),
-                RecievedAction::Fresh(data) => self.data.add_fresh_data(data),
+        self.try_state_update();
+    }
+    /// Same as [`state_update`][Communicator::state_update] but returns wether
+    /// any change or fresh data was applied, so that work depending on the
+    /// data can be skipped otherwise.
+    pub fn try_state_update(&mut self) -> bool {
+        self.call_result_callbacks();
+        self.poll_debounced_query();
+        self.poll_streamed_queries();
+        let actions = self.recive_new();
+        self.apply_actions(actions)
+    }
+    /// Stream of every change this communicator recives, the changes are
+    /// applied to the data as they flow through the stream like they would be
+    /// in [`state_update`][Communicator::state_update]. Fresh data from queries
+    /// is applied as well but not part of the stream. The stream ends once
+    /// the container was dropped.
+    pub fn change_stream(&mut self) -> impl Stream<Item = DataChange<Key, Value>> + Send + '_ {
+        stream::unfold((self, VecDeque::new()), |(comm, mut changes)| async move {
+            loop {
+                if let Some(change) = changes.pop_front() {
+                    return Some((change, (comm, changes)));
+                }
+                let actions = comm.recive_new();
+                changes.extend(actions.iter().filter_map(|action| match action {
+                    RecievedAction::Change(change) => Some(change.clone()),
+                    RecievedAction::Fresh(_) => None,
+                }));
+                if !comm.apply_actions(actions) && !comm.reciver.wait_for_new().await {
+                    return None;
+                }
             }
-            self.has_changed = true;
+        })
+    }
+    /// Sends the debounced query once its delay passed and polls it until it
+    /// resolved. It is polled here instead of spawned so that no runtime is
+    /// needed on the thread calling [`state_update`][Communicator::state_update].
+    /// A query whose delay passed supersedes the one that is still running,
+    /// the result of the older one is then never stored.
+    fn poll_debounced_query(&mut self) {
+        let due = self
+            .debounced_query
+            .get_mut()
+            .unwrap()
+            .take_if(|debounced| debounced.deadline <= Instant::now());
+        if let Some(debounced) = due {
+            trace!("Delay of debounced query [{}] passed, sending it.", debounced.query_type);
+            if self.running_debounced.is_some() {
+                trace!("Dropped the result of the debounced query that was still running.");
+            }
+            self.running_debounced = Some(self.query(debounced.query_type));
+        }
+        let Some(running) = self.running_debounced.as_mut() else {
+            return;
+        };
+        let mut context = Context::from_waker(noop_waker_ref());
+        if let Poll::Ready(result) = running.poll_unpin(&mut context) {
+            self.running_debounced = None;
+            self.debounced_result = Some(result);
+        }
+    }
+    /// Polls the streamed queries until they resolved, like
+    /// [`poll_debounced_query`][Communicator::poll_debounced_query] does.
+    fn poll_streamed_queries(&mut self) {
+        let mut context = Context::from_waker(noop_waker_ref());
+        self.running_streamed
+            .get_mut()
+            .unwrap()
+            .retain_mut(|running| running.poll_unpin(&mut context).is_pending());
+    }
+    /// Calls the callbacks of every change sent with one that resolved.
+    fn call_result_callbacks(&mut self) {
+        let finished = self
+            .result_callbacks
+            .get_mut()
+            .unwrap()
+            .drain_if(|pending| pending.promise.poll_and_check_finished());
+        for mut pending in finished {
+            let result = pending
+                .promise
+                .take_value()
+                .unwrap_or(ChangeResult::Error(ChangeError::DefaultError));
+            (pending.callback)(result);
+        }
+    }
+    fn recive_new(&mut self) -> Vec<RecievedAction<Key, Value>> {
+        self.signals.extend(self.reciver.recive_signals());
+        let actions = self.reciver.recive_new();
+        match self.equal_values {
+            Some(equal) => self.drop_equal_updates(actions, equal),
+            None => actions,
+        }
+    }
+    /// Removes every updated value that is equal to the one already held,
+    /// updates that end up empty are dropped entirely.
+    fn drop_equal_updates(
+        &self,
+        actions: Vec<RecievedAction<Key, Value>>,
+        equal: EqualityFn<Value>,
+    ) -> Vec<RecievedAction<Key, Value>> {
+        actions
+            .into_iter()
+            .filter_map(|action| match action {
+                RecievedAction::Change(DataChange::Update(mut values)) if !values.is_empty() => {
+                    values.retain(|value| {
+                        !self
+                            .data
+                            .get(value.key())
+                            .is_some_and(|current| equal(current, value))
+                    });
+                    (!values.is_empty()).then_some(DataChange::Update(values).into())
+                }
+                action => Some(action),
+            })
+            .collect()
+    }
+    /// Applies the recived actions to the data, returns wether there were any.
+    fn apply_actions(&mut self, actions: Vec<RecievedAction<Key, Value>>) -> bool {
+        if actions.is_empty() {
+            return false;
+        }
+        self.has_changed = true;
+        let total_in_storage = &mut self.total_in_storage;
+        let on_delete = &mut self.on_delete;
+        let changed_keys = &mut self.changed_keys;
+        let selected = &mut self.selected;
+        let loading_queries = &self.sender.loading_queries;
+        self.data.with_deferred_resort(|data| {
+            actions.into_iter().for_each(|action| match action {
+                RecievedAction::Change(update) => {
+                    changed_keys.extend(update.value_keys().into_iter().cloned());
+                    if let DataChange::Delete(keys) = &update {
+                        keys.iter().for_each(|key| {
+                            selected.remove(key);
+                        });
+                        if let Some(on_delete) = on_delete.as_mut() {
+                            on_delete(keys);
+                        }
+                    }
+                    data.update_data(update)
+                }
+                RecievedAction::Fresh(fresh_data) => {
+                    if let Some(query_id) = fresh_data.query_id() {
+                        loading_queries.lock().unwrap().recived(query_id, &fresh_data);
+                    }
+                    if fresh_data.stream_total().is_some() {
+                        return;
+                    }
+                    changed_keys.extend(fresh_data.keys().cloned());
+                    if let Some(total) = fresh_data.total() {
+                        *total_in_storage = Some(total);
+                    }
+                    data.add_fresh_data(fresh_data)
+                }
+            });
         });
+        if let Some(map_watch) = self.map_watch.get() {
+            map_watch.send_replace(self.data.map().clone());
+        }
+        if let Some(on_data_available) = &self.on_data_available {
+            on_data_available();
+        }
+        true
+    }
+    /// Returns and clears the signals the container broadcast, see
+    /// [`DataContainer::broadcast_signal`][crate::container::DataContainer::broadcast_signal].
+    pub fn take_signals(&mut self) -> Vec<Signal> {
+        std::mem::take(&mut self.signals)
+    }
+    /// Number of changes sent from this communicator that the container
+    /// hasn't answered yet, e.g. to show how many changes are still unsaved.
+    pub fn pending_changes(&self) -> usize {
+        self.sender.pending_changes.load(AtomicOrdering::SeqCst)
+    }
+    /// Wether a query was sent from this communicator whose data hasn't been
+    /// recived in [`state_update`][Communicator::state_update] yet, e.g. to
+    /// show a loading spinner. A query whose result was superseded by a newer
+    /// query of the same kind stops loading once that newer query delivers.
+    pub fn is_loading(&self) -> bool {
+        !self.sender.loading_queries.lock().unwrap().queries.is_empty()
+    }
+    /// If set, updates to values that are equal to the ones already held are
+    /// dropped in [`state_update`][Communicator::state_update], so that they
+    /// neither cause a resort nor flag the data as changed.
+    pub fn skip_equal_updates(&mut self, skip: bool) -> &mut Self
+    where
+        Value: PartialEq,
+    {
+        self.equal_values = skip.then_some(<Value as PartialEq>::eq as EqualityFn<Value>);
+        self
+    }
+    /// Sets a callback that is called in [`state_update`][Communicator::state_update]
+    /// with the keys of every delete, right before they are removed.
+    pub fn on_delete(&mut self, f: impl FnMut(&[Key]) + Send + 'static) {
+        self.on_delete = Some(Box::new(f));
+    }
+    /// Watch of all locally held values, a new copy of them is sent after
+    /// every [`state_update`][Communicator::state_update] that applied new
+    /// data. Meant for consumers that prefer to be pushed the latest state,
+    /// once this was called every such update clones the whole map.
+    pub fn watch_map(&self) -> watch::Receiver<HashMap<Key, Value>> {
+        self.map_watch
+            .get_or_init(|| watch::channel(self.data.map().clone()).0)
+            .subscribe()
+    }
+    /// Sets a callback that is called at the end of every
+    /// [`state_update`][Communicator::state_update] that applied new data, e.g.
+    /// to request a repaint instead of redrawing every frame.
+    pub fn on_data_available(&mut self, f: impl Fn() + Send + Sync + 'static) {
+        self.on_data_available = Some(Box::new(f));
+    }
+    /// Keeps reciving updates until this communicator doesn't hold any data
+    /// anymore. The container still has to be updated for this to resolve.
+    pub async fn await_empty(&mut self) {
+        loop {
+            self.state_update();
+            if self.is_empty() {
+                return;
+            }
+            tokio::time::sleep(AWAIT_POLL_INTERVAL).await;
+        }
+    }
+    /// Keeps reciving updates until this communicator holds a value with the
+    /// key and returns it, e.g. to react once an insert was confirmed. The
+    /// container still has to be updated for this to resolve.
+    pub async fn await_key(&mut self, key: Key) -> Value {
+        loop {
+            self.state_update();
+            if let Some(value) = self.data.get(&key) {
+                return value.clone();
+            }
+            tokio::time::sleep(AWAIT_POLL_INTERVAL).await;
+        }
     }
     pub fn query(
         &self,
@@ -114,6 +451,81 @@ where
         trace!("Recived query command.");
         self.sender.send_query(self.uuid, query_type)
     }
+    /// Sends the query once `delay` passed without another debounced query,
+    /// which replaces this one, e.g. so that typing into a search field only
+    /// queries once the user stopped. The query is sent during the first
+    /// [`state_update`][Communicator::state_update] after the delay, its
+    /// result is available through [`debounced_result`][Communicator::debounced_result].
+    pub fn query_debounced(&self, query_type: QueryType<Key, Value>, delay: Duration) {
+        trace!("Recived debounced query [{query_type}].");
+        *self.debounced_query.lock().unwrap() = Some(DebouncedQuery {
+            query_type,
+            deadline: Instant::now() + delay,
+        });
+    }
+    /// Result of the last debounced query that resolved, `None` until the
+    /// first one did.
+    pub fn debounced_result(&self) -> Option<&Result<QueryResult, BoxedSendError>> {
+        self.debounced_result.as_ref()
+    }
+    /// Shorthand for a [`query`][Communicator::query] with
+    /// [`QueryType::predicate`].
+    pub fn query_where(
+        &self,
+        pred: impl Fn(&Value) -> bool + Send + Sync + 'static,
+    ) -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
+        self.query(QueryType::predicate(pred))
+    }
+    /// Same as [`query`][Communicator::query] but a [`Priority::High`] query
+    /// is handled by the container before any normal queries that are waiting.
+    pub fn query_priority(
+        &self,
+        query_type: QueryType<Key, Value>,
+        priority: Priority,
+    ) -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
+        trace!("Recived query command with priority [{priority:?}].");
+        match priority {
+            Priority::Normal => self.sender.send_query(self.uuid, query_type),
+            Priority::High => self.sender.send_priority_query(self.uuid, query_type),
+        }
+    }
+    /// Queries for data like [`query`][Communicator::query] but resolves to
+    /// the returned values directly. The values are still also added to the
+    /// data of this communicator.
+    pub fn fetch(
+        &self,
+        query_type: QueryType<Key, Value>,
+    ) -> BoxFuture<'static, Result<Vec<Value>, QueryError>> {
+        trace!("Recived fetch command.");
+        self.sender.send_fetch(self.uuid, query_type)
+    }
+    /// Only queries if this communicator doesn't hold any data yet, otherwise
+    /// resolves to [`QueryResult::Success`] right away.
+    pub fn query_if_empty(
+        &self,
+        query_type: QueryType<Key, Value>,
+    ) -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
+        if !self.is_empty() {
+            trace!("Skipped query since the communicator already holds data.");
+            return Box::pin(async { Ok(QueryResult::Success) });
+        }
+        self.query(query_type)
+    }
+    /// Queries for data like [`query`][Communicator::query] but the storage
+    /// may return the result in chunks, see
+    /// [`Storage::query_streamed`][crate::container::storage::Storage::query_streamed].
+    /// Every chunk is added to the data as it arrives, the returned handle
+    /// tracks how far along the query is, e.g. for a progress bar. The query
+    /// is polled during [`state_update`][Communicator::state_update] instead
+    /// of spawned, so no runtime is needed.
+    pub fn query_streamed(&self, query_type: QueryType<Key, Value>) -> QueryProgress {
+        trace!("Recived streamed query command.");
+        let (progress, mut running) = self.sender.send_streamed_query(self.uuid, query_type);
+        if running.poll_unpin(&mut Context::from_waker(noop_waker_ref())).is_pending() {
+            self.running_streamed.lock().unwrap().push(running);
+        }
+        progress
+    }
     pub fn query_action(
         &self,
         query_type: QueryType<Key, Value>,
@@ -128,6 +540,17 @@ where
         self.sender
             .send_change(self.uuid, ChangeType::Insert(val))
     }
+    /// Sends the insert and calls `f` with the result once it resolved, during
+    /// the next [`state_update`][Communicator::state_update] after that. Meant
+    /// for immediate mode UIs where the change can't be awaited.
+    pub fn insert_then(&self, value: Value, f: impl FnOnce(ChangeResult) + Send + 'static) {
+        trace!("Recived insert command with a callback.");
+        let change_future = self.sender.send_change(self.uuid, ChangeType::Insert(value));
+        self.result_callbacks.lock().unwrap().push(ResultCallback {
+            promise: ImmediateValuePromise::new(change_future),
+            callback: Box::new(f),
+        });
+    }
     pub fn insert_action(
         &self,
     ) -> impl FnMut(Value) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
@@ -148,6 +571,18 @@ where
         let mut action = self.sender.send_change_action(self.uuid);
         move |values: Vec<Value>| action(ChangeType::InsertMany(values))
     }
+    /// Inserts only the values whose key doesn't exist in the storage yet,
+    /// e.g. to seed defaults. Only the inserted values are sent to the
+    /// communicators and their number is returned as
+    /// [`ChangeResult::Affected`].
+    pub fn insert_if_absent_many(
+        &self,
+        vals: Vec<Value>,
+    ) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
+        trace!("Recived insert if absent command.");
+        self.sender
+            .send_change(self.uuid, ChangeType::InsertIfAbsentMany(vals))
+    }
     pub fn update(&self, val: Value) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
         trace!("Recived update command.");
         self.sender.send_change(self.uuid, ChangeType::Update(val))
@@ -194,32 +629,460 @@ where
         let mut action = self.sender.send_change_action(self.uuid);
         move |keys: Vec<Key>| action(ChangeType::DeleteMany(keys))
     }
+    /// Moves the value with `key` between `after` and `before`. The new position
+    /// is decided by the storage, see [`Storage::reorder`][crate::container::storage::Storage::reorder].
+    pub fn reorder(
+        &self,
+        key: Key,
+        before: Option<Key>,
+        after: Option<Key>,
+    ) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
+        trace!("Recived reorder command.");
+        self.sender
+            .send_change(self.uuid, ChangeType::Move { key, before, after })
+    }
+    /// Applies `f` to the value with `key` inside of the storage, e.g. to
+    /// increment a counter without racing other communicators. See
+    /// [`ChangeType::Modify`].
+    pub fn modify(
+        &self,
+        key: Key,
+        f: impl Fn(&mut Value) + Send + Sync + 'static,
+    ) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
+        trace!("Recived modify command.");
+        self.sender
+            .send_change(self.uuid, ChangeType::Modify { key, f: Arc::new(f) })
+    }
+    /// Moves the value with the key `old` to the key `new`, see
+    /// [`ChangeType::Rekey`].
+    pub fn rekey(
+        &self,
+        old: Key,
+        new: Key,
+    ) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>>
+    where
+        Value: SetKey<Key>,
+    {
+        trace!("Recived rekey command.");
+        let new_key = new.clone();
+        let set_key = Arc::new(move |value: &mut Value| value.set_key(new_key.clone()));
+        self.sender
+            .send_change(self.uuid, ChangeType::Rekey { old, new, set_key })
+    }
+    /// Moves every value from the first to the second key of each pair, as one
+    /// [`rekey`][Communicator::rekey] per pair.
+    pub fn map_keys(&self, renames: impl IntoIterator<Item = (Key, Key)>) -> ActionBatch
+    where
+        Value: SetKey<Key>,
+    {
+        renames
+            .into_iter()
+            .map(|(old, new)| self.rekey(old, new))
+            .collect()
+    }
+    /// Applies the change to the local data right away, without sending it to
+    /// the container. The change is kept until it is sent with
+    /// [`flush_offline`][Communicator::flush_offline].
+    ///
+    /// A [`ChangeType::Move`], [`ChangeType::Modify`], [`ChangeType::Rekey`]
+    /// or [`ChangeType::InsertIfAbsentMany`] is only resolved by the storage,
+    /// so it is queued without changing the local data.
+    pub fn queue_offline_change(&mut self, change: ChangeType<Key, Value>) {
+        trace!("Queued offline change [{change}].");
+        if let Ok(data_change) = DataChange::try_from(change.clone()) {
+            self.changed_keys
+                .extend(data_change.value_keys().into_iter().cloned());
+            self.data.update_data(data_change);
+            self.has_changed = true;
+        }
+        self.offline_changes.lock().unwrap().push(change);
+    }
+    /// Drops every locally held value that doesn't match the query, e.g. to
+    /// show the result of a narrower filter right away while the new query is
+    /// still loading. The storage isn't asked, returns the number of values
+    /// that were dropped. Queries that can't be checked for single values,
+    /// see [`QueryType::matches_per_value`], don't drop anything.
+    pub fn prune_to_query(&mut self, query: &QueryType<Key, Value>) -> usize {
+        if !query.matches_per_value() {
+            return 0;
+        }
+        let keys = self
+            .data
+            .iter()
+            .filter(|value| !query.apply(value))
+            .map(|value| value.key().clone())
+            .collect_vec();
+        let pruned = keys.len();
+        if pruned > 0 {
+            trace!("Pruned {pruned} values not matching [{query}].");
+            self.changed_keys.extend(keys.iter().cloned());
+            self.data.delete(keys);
+            self.has_changed = true;
+        }
+        pruned
+    }
+    /// Number of changes waiting for [`flush_offline`][Communicator::flush_offline].
+    pub fn offline_changes(&self) -> usize {
+        self.offline_changes.lock().unwrap().len()
+    }
+    /// Sends all changes queued with
+    /// [`queue_offline_change`][Communicator::queue_offline_change] to the
+    /// container. Every change is only sent once the previous one resolved, so
+    /// they are applied in the order they were queued in. If a change can't be
+    /// sent to the container, the flush stops with its error as the last
+    /// result and it is queued again together with all changes after it.
+    pub fn flush_offline(
+        &mut self,
+    ) -> BoxFuture<'static, Result<Vec<ChangeResult>, BoxedSendError>> {
+        let offline_changes = self.offline_changes.clone();
+        let changes = std::mem::take(&mut *offline_changes.lock().unwrap());
+        trace!("Flushing {} offline changes.", changes.len());
+        let mut action = self.sender.send_change_action(self.uuid);
+        Box::pin(async move {
+            let mut results = Vec::with_capacity(changes.len());
+            for (index, change) in changes.iter().enumerate() {
+                let result = action(change.clone()).await;
+                let unsent = matches!(
+                    result,
+                    Err(_) | Ok(ChangeResult::Error(ChangeError::ChannelSendError(_)))
+                );
+                if unsent {
+                    // NOTE: changes queued during the flush go after these
+                    offline_changes
+                        .lock()
+                        .unwrap()
+                        .splice(0..0, changes[index..].iter().cloned());
+                }
+                results.push(result?);
+                if unsent {
+                    break;
+                }
+            }
+            Ok(results)
+        })
+    }
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
     pub fn is_empty(&self) -> bool {
         self.data.data.is_empty()
     }
+    pub fn sorted_position(&self, key: &Key) -> Option<usize> {
+        self.data.sorted_position(key)
+    }
+    /// Key of the value, without needing [`GetKey`][crate::GetKey] in scope.
+    pub fn key_of<'a>(&self, value: &'a Value) -> &'a Key {
+        value.key()
+    }
+    /// Same as [`sorted_position`][Communicator::sorted_position], the
+    /// counterpart to [`nth_sorted`][Communicator::nth_sorted].
+    pub fn index_of_key(&self, key: &Key) -> Option<usize> {
+        self.sorted_position(key)
+    }
+    /// Value at index `n` of the sorted data, e.g. for keyboard navigation.
+    pub fn nth_sorted(&self, n: usize) -> Option<&Value> {
+        self.data.nth_sorted(n)
+    }
+    /// Number of values the storage holds overall, as last reported with a
+    /// [`QueryResult::Page`]. Unlike [`Data::len`] this is not limited to the
+    /// values this communicator holds.
+    pub fn total_in_storage(&self) -> Option<usize> {
+        self.total_in_storage
+    }
+    /// Same as [`Data::stats`].
+    pub fn stats(&self) -> DataStats {
+        self.data.stats()
+    }
+    /// Immutable view of the data that is not affected by any later
+    /// [`state_update`][Communicator::state_update].
+    pub fn snapshot(&self) -> DataSnapshot<Key, Value> {
+        self.data.snapshot()
+    }
+    /// Maps every value in sorted order, e.g. to build the view models of a
+    /// table.
+    pub fn map_sorted<T>(&self, f: impl FnMut(&Value) -> T) -> Vec<T> {
+        self.data.sorted_iter().map(f).collect()
+    }
+    /// The keys in the current sort order, unlike [`Data::keys_cloned`]. Can
+    /// be kept to pass to [`sorted_diff`][Communicator::sorted_diff] later.
+    pub fn sorted_keys(&self) -> Vec<Key> {
+        self.map_sorted(|value| value.key().clone())
+    }
+    /// Same as [`Data::page_sorted_by`].
+    pub fn page_sorted_by(
+        &self,
+        cmp: impl Fn(&Value, &Value) -> Ordering,
+        page: usize,
+        per_page: usize,
+    ) -> Option<Vec<&Value>> {
+        self.data.page_sorted_by(cmp, page, per_page)
+    }
+    /// Same as [`Data::sorted_diff`].
+    pub fn sorted_diff(&self, previous: &[Key]) -> Vec<Move<Key>> {
+        self.data.sorted_diff(previous)
+    }
+    /// Same as [`Data::values_between`].
+    pub fn values_between(&self, lo: &Value, hi: &Value) -> Vec<&Value> {
+        self.data.values_between(lo, hi)
+    }
+    /// Sorted values around the value with the key `center`, see [`Data::window`].
+    pub fn window(&self, center: &Key, radius: usize) -> Vec<&Value> {
+        self.data.window(center, radius)
+    }
+    /// Sorted and paginated view of the data, see [`Data::page`].
+    pub fn data_sorted_page(&self, page: usize, per_page: usize) -> Option<Vec<&Value>> {
+        self.data.page(page, per_page)
+    }
     pub fn sort<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(&mut self, sorting_fn: F) {
         self.data.new_sorting_fn(sorting_fn);
     }
+    /// Goes back to sorting by key, which is the order before any
+    /// [`sort`][Communicator::sort] was set.
+    pub fn sort_by_key_default(&mut self) {
+        self.data.reset_sorting_fn();
+    }
+    /// Adds a named sort view next to the main order, so switching between
+    /// views doesn't replace the main sort. Read it with
+    /// [`sorted_view`][Communicator::sorted_view], which resorts the view
+    /// only if the data changed since it was last read.
+    pub fn add_sort_view<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(
+        &mut self,
+        name: SortId,
+        sorting_fn: F,
+    ) {
+        self.data.add_sort_view(name, sorting_fn);
+    }
+    pub fn sorted_view(&self, name: SortId) -> Option<Vec<&Value>> {
+        self.data.sorted_view(name)
+    }
+    /// Like [`sort`][Communicator::sort] but skips the resort if the sorting
+    /// function with the same `sort_id` is already installed. Useful when the
+    /// same sort is set up again every frame.
+    pub fn sort_keyed<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(
+        &mut self,
+        sort_id: SortId,
+        sorting_fn: F,
+    ) {
+        self.data.new_keyed_sorting_fn(sort_id, sorting_fn);
+    }
     
     pub fn has_changed(&self) -> bool {
         self.has_changed
     }
     pub fn set_viewed(&mut self) -> &mut Self {
         self.has_changed = false;
+        self.changed_keys.clear();
+        self
+    }
+    /// Like [`set_viewed`][Communicator::set_viewed] but only for the given
+    /// keys, e.g. the rows that were actually rendered. The data only counts
+    /// as viewed once no changed keys are left.
+    pub fn set_viewed_keys(&mut self, keys: &[Key]) -> &mut Self {
+        keys.iter().for_each(|key| {
+            self.changed_keys.remove(key);
+        });
+        if self.changed_keys.is_empty() {
+            self.has_changed = false;
+        }
+        self
+    }
+    /// Keys of all values that were added, updated or deleted since the data
+    /// was last viewed.
+    pub fn changed_keys(&self) -> &HashSet<Key> {
+        &self.changed_keys
+    }
+    /// Adds the key to the selection, e.g. of a table. Deleted keys are
+    /// removed from the selection in [`state_update`][Communicator::state_update].
+    pub fn select(&mut self, key: Key) -> &mut Self {
+        self.selected.insert(key);
         self
     }
+    pub fn deselect(&mut self, key: &Key) -> &mut Self {
+        self.selected.remove(key);
+        self
+    }
+    /// Selects the key if it isn't selected and deselects it otherwise,
+    /// returns wether it is selected now.
+    pub fn toggle_select(&mut self, key: Key) -> bool {
+        if self.selected.remove(&key) {
+            return false;
+        }
+        self.selected.insert(key);
+        true
+    }
+    pub fn selected(&self) -> &HashSet<Key> {
+        &self.selected
+    }
+    /// The selected values that are held, in the sorted order.
+    pub fn selected_values(&self) -> Vec<&Value> {
+        self.data
+            .sorted_iter()
+            .filter(|value| self.selected.contains(value.key()))
+            .collect_vec()
+    }
+    /// Wether the value with the key was added, updated or deleted since the
+    /// data was last viewed, e.g. so that a row only re-renders if needed.
+    pub fn has_key_changed(&self, key: &Key) -> bool {
+        self.changed_keys.contains(key)
+    }
+    /// Returns the keys that changed since the data was last viewed and marks
+    /// the data as viewed, see [`set_viewed`][Communicator::set_viewed].
+    pub fn take_changed(&mut self) -> HashSet<Key> {
+        self.has_changed = false;
+        std::mem::take(&mut self.changed_keys)
+    }
     pub fn data(&self) -> Vec<&Value> {
         self.data.data.values().collect_vec()
     }
+    /// Same as [`Data::get_or`].
+    pub fn get_or<'a>(&'a self, key: &Key, default: &'a Value) -> &'a Value {
+        self.data.get_or(key, default)
+    }
+    /// Same as [`Data::filtered`].
+    pub fn data_filtered(&self, pred: impl Fn(&Value) -> bool) -> Vec<&Value> {
+        self.data.filtered(pred)
+    }
+    /// Number of values this communicator holds that match the predicate.
+    pub fn count_local(&self, pred: impl Fn(&Value) -> bool) -> usize {
+        self.data.iter().filter(|value| pred(value)).count()
+    }
+    /// Keys of all values this communicator holds.
+    pub fn key_set(&self) -> HashSet<&Key> {
+        self.data.keys_iter().collect()
+    }
+    /// Wether this and the other communicator hold any value with the same key.
+    pub fn shares_keys_with(&self, other: &Self) -> bool {
+        self.data.keys_iter().any(|key| other.data.map().contains_key(key))
+    }
+    /// Wether every key this communicator holds is also held by the other one.
+    pub fn is_subset_of(&self, other: &Self) -> bool {
+        self.data.keys_iter().all(|key| other.data.map().contains_key(key))
+    }
+    /// The locally held data as a JSON object, e.g. to log it during
+    /// development. The keys have to serialize to strings or numbers, if the
+    /// data can't be serialized the error message is returned instead.
+    #[cfg(feature = "serde")]
+    pub fn data_json(&self) -> String
+    where
+        Key: serde::Serialize,
+        Value: serde::Serialize,
+    {
+        serde_json::to_string(self.data.map()).unwrap_or_else(|err| err.to_string())
+    }
+    /// The data ordered by key, independent of the sorting function.
+    pub fn data_btreemap(&self) -> BTreeMap<Key, &Value> {
+        self.data
+            .data
+            .iter()
+            .map(|(key, value)| (key.clone(), value))
+            .collect()
+    }
 }
 
-struct Sender<Key, Value>
+/// Sending half of a [`Communicator`], see [`into_parts`][Communicator::into_parts].
+pub struct Sender<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
     change_sender: mpsc::Sender<Change<Key, Value>>,
     query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+    priority_query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+    pending_changes: Arc<AtomicUsize>,
+    loading_queries: Arc<Mutex<LoadingQueries<Key, Value>>>,
+}
+
+/// Queries that were sent but whose data wasn't recived yet, identified by
+/// an id that the container passes back along with the data.
+struct LoadingQueries<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    next_id: u64,
+    queries: Vec<(u64, Discriminant<QueryType<Key, Value>>)>,
+    /// Progress of the streamed queries, they stop loading once every chunk
+    /// was recived.
+    streamed: HashMap<u64, QueryProgress>,
+}
+
+impl<Key, Value> LoadingQueries<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    fn start(&mut self, query_type: &QueryType<Key, Value>) -> u64 {
+        let query_id = self.next_id;
+        self.next_id += 1;
+        self.queries.push((query_id, std::mem::discriminant(query_type)));
+        query_id
+    }
+    /// The data of the query arrived. Older queries of the same kind are done
+    /// as well, since the container might have dropped their data.
+    fn finish(&mut self, query_id: u64) {
+        let Some(kind) = self
+            .queries
+            .iter()
+            .find(|(id, _)| *id == query_id)
+            .map(|(_, kind)| *kind)
+        else {
+            return;
+        };
+        self.queries
+            .retain(|(id, other_kind)| *id > query_id || *other_kind != kind);
+    }
+    /// The query failed, so no data will arrive for it.
+    fn cancel(&mut self, query_id: u64) {
+        self.queries.retain(|(id, _)| *id != query_id);
+        self.streamed.remove(&query_id);
+    }
+    /// Data for the query arrived, for a streamed query this might only be
+    /// one of its chunks.
+    fn recived(&mut self, query_id: u64, fresh_data: &FreshData<Key, Value>) {
+        let Some(progress) = self.streamed.get(&query_id) else {
+            self.finish(query_id);
+            return;
+        };
+        match fresh_data.stream_total() {
+            Some(total) => progress.end(total),
+            None => progress.add_chunk(fresh_data.len()),
+        }
+        if progress.is_done() {
+            self.streamed.remove(&query_id);
+            self.finish(query_id);
+        }
+    }
+}
+
+impl<Key, Value> Default for LoadingQueries<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            queries: vec![],
+            streamed: HashMap::new(),
+        }
+    }
+}
+
+/// Counts a change as pending for as long as it is alive, so that a change
+/// future that is dropped before it resolved is not counted forever.
+struct PendingChange(Arc<AtomicUsize>);
+
+impl PendingChange {
+    fn new(pending_changes: &Arc<AtomicUsize>) -> Self {
+        pending_changes.fetch_add(1, AtomicOrdering::SeqCst);
+        Self(pending_changes.clone())
+    }
+}
+
+impl Drop for PendingChange {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, AtomicOrdering::SeqCst);
+    }
 }
 
 impl<Key, Value> Sender<Key, Value>
@@ -231,10 +1094,14 @@ where
     fn new(
         change_sender: mpsc::Sender<Change<Key, Value>>,
         query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+        priority_query_sender: mpsc::Sender<DataQuery<Key, Value>>,
     ) -> Self {
         Self {
             change_sender,
             query_sender,
+            priority_query_sender,
+            pending_changes: Arc::new(AtomicUsize::new(0)),
+            loading_queries: Arc::new(Mutex::new(LoadingQueries::default())),
         }
     }
 
@@ -244,7 +1111,8 @@ where
         action_type: ChangeType<Key, Value>,
     ) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>> {
         let new_sender = self.change_sender.clone();
-        Box::pin(Self::change_future(origin_uuid, new_sender, action_type))
+        let pending = PendingChange::new(&self.pending_changes);
+        Box::pin(Self::change_future(origin_uuid, new_sender, action_type, pending))
     }
 
     fn send_change_action(
@@ -253,9 +1121,11 @@ where
     ) -> impl FnMut(ChangeType<Key, Value>) -> BoxFuture<'static, Result<ChangeResult, BoxedSendError>>
     {
         let new_sender = self.change_sender.clone();
+        let pending_changes = self.pending_changes.clone();
         move |action_type: ChangeType<Key, Value>| {
             let cloned_sender = new_sender.clone();
-            Box::pin(Self::change_future(origin_uuid, cloned_sender, action_type))
+            let pending = PendingChange::new(&pending_changes);
+            Box::pin(Self::change_future(origin_uuid, cloned_sender, action_type, pending))
         }
     }
 
@@ -263,6 +1133,7 @@ where
         origin_uuid: Uuid,
         new_sender: mpsc::Sender<Change<Key, Value>>,
         action_type: ChangeType<Key, Value>,
+        pending: PendingChange,
     ) -> impl std::future::Future<Output = Result<ChangeResult, BoxedSendError>> {
         async move {
             let action_type_str = format!("{action_type}");
@@ -283,6 +1154,7 @@ where
                     ChangeResult::Error(ChangeError::send_err(&err))
                 }
             };
+            drop(pending);
             info!(
                 msg = format!(
                     "Result for change type [{action_type_str}] was returned, is [{response:?}]"
@@ -299,7 +1171,17 @@ where
         query_type: QueryType<Key, Value>,
     ) -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
         let new_sender = self.query_sender.clone();
-        Box::pin(Self::query_future(new_sender, origin_uuid, query_type))
+        let loading_queries = self.loading_queries.clone();
+        Box::pin(Self::query_future(new_sender, loading_queries, origin_uuid, query_type))
+    }
+    fn send_priority_query(
+        &self,
+        origin_uuid: Uuid,
+        query_type: QueryType<Key, Value>,
+    ) -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
+        let new_sender = self.priority_query_sender.clone();
+        let loading_queries = self.loading_queries.clone();
+        Box::pin(Self::query_future(new_sender, loading_queries, origin_uuid, query_type))
     }
     fn send_query_action(
         &self,
@@ -307,17 +1189,119 @@ where
         query_type: QueryType<Key, Value>,
     ) -> impl FnOnce() -> BoxFuture<'static, Result<QueryResult, BoxedSendError>> {
         let new_sender = self.query_sender.clone();
-        move || Box::pin(Self::query_future(new_sender, origin_uuid, query_type))
+        let loading_queries = self.loading_queries.clone();
+        move || Box::pin(Self::query_future(new_sender, loading_queries, origin_uuid, query_type))
+    }
+
+    /// Returns the progress of the query together with the future that sends
+    /// it and fails the progress if the query fails.
+    fn send_streamed_query(
+        &self,
+        origin_uuid: Uuid,
+        query_type: QueryType<Key, Value>,
+    ) -> (QueryProgress, BoxFuture<'static, ()>) {
+        let progress = QueryProgress::default();
+        if query_type.is_empty() {
+            trace!(
+                msg = format!("Streamed query [{query_type}] can't match any value, skipped it."),
+                comm = origin_uuid.to_string()
+            );
+            progress.end(0);
+            return (progress, Box::pin(async {}));
+        }
+        let query_id = {
+            let mut loading_queries = self.loading_queries.lock().unwrap();
+            let query_id = loading_queries.start(&query_type);
+            loading_queries.streamed.insert(query_id, progress.clone());
+            query_id
+        };
+        let query_type_str = format!("{query_type}");
+        let (mut query, reciver) = DataQuery::from_type(origin_uuid, query_type);
+        query.query_id = Some(query_id);
+        query.streamed = true;
+
+        let new_sender = self.query_sender.clone();
+        let loading_queries = self.loading_queries.clone();
+        let task_progress = progress.clone();
+        let running = Box::pin(async move {
+            let response = match new_sender.send(query).await {
+                Ok(()) => reciver.await.into(),
+                Err(err) => QueryResult::Error(QueryError::send(&err)),
+            };
+            info!(
+                msg = format!(
+                    "Result for streamed query [{query_type_str}] was returned, is [{response:?}]"
+                ),
+                comm = origin_uuid.to_string()
+            );
+            if let QueryResult::Error(err) = response {
+                loading_queries.lock().unwrap().cancel(query_id);
+                task_progress.fail(err);
+            }
+        });
+        (progress, running)
+    }
+
+    fn send_fetch(
+        &self,
+        origin_uuid: Uuid,
+        query_type: QueryType<Key, Value>,
+    ) -> BoxFuture<'static, Result<Vec<Value>, QueryError>> {
+        let new_sender = self.query_sender.clone();
+        Box::pin(async move {
+            if query_type.is_empty() {
+                trace!(
+                    msg = format!("Fetch [{query_type}] can't match any value, skipped it."),
+                    comm = origin_uuid.to_string()
+                );
+                return Ok(vec![]);
+            }
+            let query_type_str = format!("{query_type}");
+            let (query, reciver, fresh_data_reciver) =
+                DataQuery::fetching_from_type(origin_uuid, query_type);
+            if let Err(err) = new_sender.send(query).await {
+                trace!(
+                    msg = format!("Fetch [{query_type_str}] returned an error [{err}]"),
+                    comm = origin_uuid.to_string()
+                );
+                return Err(QueryError::send(&err));
+            }
+            debug!(
+                msg = format!("Fetch [{query_type_str}] was sent now awaiting response."),
+                comm = origin_uuid.to_string()
+            );
+            match QueryResult::from(reciver.await) {
+                QueryResult::Success | QueryResult::Page { .. } => fresh_data_reciver
+                    .await
+                    .map(|fresh_data| fresh_data.values().cloned().collect_vec())
+                    .map_err(QueryError::ChannelRecive),
+                // NOTE: a group count doesn't return any values
+                QueryResult::Groups(_) => Ok(vec![]),
+                QueryResult::Error(err) => Err(err),
+            }
+        })
     }
 
     fn query_future(
         new_sender: mpsc::Sender<DataQuery<Key, Value>>,
+        loading_queries: Arc<Mutex<LoadingQueries<Key, Value>>>,
         origin_uuid: Uuid,
         query_type: QueryType<Key, Value>,
     ) -> impl std::future::Future<Output = Result<QueryResult, BoxedSendError>> {
         async move {
+            // NOTE: same as for empty changes in `Storage::handle_change`, this
+            // saves the round trip to the storage
+            if query_type.is_empty() {
+                trace!(
+                    msg = format!("Query [{query_type}] can't match any value, skipped it."),
+                    comm = origin_uuid.to_string()
+                );
+                return Ok(QueryResult::Success);
+            }
             let query_type_str = format!("{query_type}");
-            let (query, reciver) = DataQuery::from_type(origin_uuid, query_type);
+            let query_id = loading_queries.lock().unwrap().start(&query_type);
+            let (mut query, reciver) = DataQuery::from_type(origin_uuid, query_type);
+            query.query_id = Some(query_id);
             let response = match new_sender.send(query).await {
                 Ok(()) => {
                     debug!(
@@ -334,6 +1318,10 @@ where
                     QueryResult::Error(QueryError::send(&err))
                 }
             };
+            // NOTE: neither an error nor a group count is followed by any data
+            if let QueryResult::Error(_) | QueryResult::Groups(_) = response {
+                loading_queries.lock().unwrap().cancel(query_id);
+            }
             info!(
                 msg = format!(
                     "Result for query type [{query_type_str}] was returned, is [{response:?}]"
@@ -345,13 +1333,22 @@ where
     }
 }
 
-struct Reciver<Key, Value>
+/// Reciving half of a [`Communicator`], see [`into_parts`][Communicator::into_parts].
+pub struct Reciver<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
-    change_reciver: mpsc::Receiver<DataChange<Key, Value>>,
+    /// The container replaces the change channel when it grows it, the old
+    /// recivers are kept until everything sent through them was recived.
+    change_recivers: VecDeque<mpsc::Receiver<DataChange<Key, Value>>>,
+    grown_change_reciver: mpsc::UnboundedReceiver<mpsc::Receiver<DataChange<Key, Value>>>,
     fresh_data_reciver: mpsc::Receiver<FreshData<Key, Value>>,
+    signal_reciver: mpsc::UnboundedReceiver<Signal>,
+    /// Change or fresh data recived by [`wait_for_new`][Reciver::wait_for_new].
+    woken_by: Option<RecievedAction<Key, Value>>,
+    /// Signal recived by [`wait_for_new`][Reciver::wait_for_new].
+    woken_by_signal: Option<Signal>,
 }
 
 impl<Key, Value> Reciver<Key, Value>
@@ -362,19 +1359,69 @@ where
     #[must_use]
     fn new(
         change_reciver: mpsc::Receiver<DataChange<Key, Value>>,
+        grown_change_reciver: mpsc::UnboundedReceiver<mpsc::Receiver<DataChange<Key, Value>>>,
         fresh_data_reciver: mpsc::Receiver<FreshData<Key, Value>>,
+        signal_reciver: mpsc::UnboundedReceiver<Signal>,
     ) -> Self {
         Self {
-            change_reciver,
+            change_recivers: VecDeque::from([change_reciver]),
+            grown_change_reciver,
             fresh_data_reciver,
+            signal_reciver,
+            woken_by: None,
+            woken_by_signal: None,
         }
     }
+    /// Waits until any channel recived something new. A change or fresh data
+    /// is kept and returned first by the next
+    /// [`recive_new`][Reciver::recive_new], a signal by the next
+    /// [`recive_signals`][Reciver::recive_signals]. Returns `false` once the
+    /// container closed every channel.
+    async fn wait_for_new(&mut self) -> bool {
+        if self.woken_by.is_some() || self.woken_by_signal.is_some() {
+            return true;
+        }
+        // NOTE: new changes are only sent through the latest change channel
+        let Some(change_reciver) = self.change_recivers.back_mut() else {
+            return false;
+        };
+        tokio::select! {
+            Some(change) = change_reciver.recv() => self.woken_by = Some(change.into()),
+            Some(fresh_data) = self.fresh_data_reciver.recv() => {
+                self.woken_by = Some(fresh_data.into());
+            }
+            Some(reciver) = self.grown_change_reciver.recv() => {
+                self.change_recivers.push_back(reciver);
+            }
+            Some(signal) = self.signal_reciver.recv() => self.woken_by_signal = Some(signal),
+            else => return false,
+        }
+        true
+    }
+    /// Tries to recive all new signals
+    fn recive_signals(&mut self) -> Vec<Signal> {
+        let mut signals = self.woken_by_signal.take().into_iter().collect_vec();
+        while let Ok(signal) = self.signal_reciver.try_recv() {
+            signals.push(signal);
+        }
+        signals
+    }
     /// Tries to recive all new Updates
     #[must_use]
     fn recive_new(&mut self) -> Vec<RecievedAction<Key, Value>> {
-        let mut new_updates: Vec<RecievedAction<Key, Value>> = vec![];
-        while let Ok(val) = self.change_reciver.try_recv() {
-            new_updates.push(val.into());
+        let mut new_updates: Vec<RecievedAction<Key, Value>> =
+            self.woken_by.take().into_iter().collect();
+        while let Ok(reciver) = self.grown_change_reciver.try_recv() {
+            self.change_recivers.push_back(reciver);
+        }
+        while let Some(reciver) = self.change_recivers.front_mut() {
+            match reciver.try_recv() {
+                Ok(val) => new_updates.push(val.into()),
+                Err(TryRecvError::Disconnected) if self.change_recivers.len() > 1 => {
+                    self.change_recivers.pop_front();
+                }
+                Err(_) => break,
+            }
         }
         while let Ok(val) = self.fresh_data_reciver.try_recv() {
             new_updates.push(val.into());
@@ -1,4 +1,4 @@
-use std::{cmp::Ordering, collections::HashMap};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, sync::Arc};
 
 use itertools::Itertools;
 use permutation::Permutation;
@@ -7,15 +7,69 @@ use tracing::{trace, warn};
 use crate::{change::DataChange, query::FreshData, KeyBounds, ValueBounds};
 
 type SortingFn<Value> = Box<dyn FnMut(&Value, &Value) -> Ordering + Send + 'static>;
+/// Identifies a sorting function, since closures themselves can't be compared.
+pub type SortId = &'static str;
 
 pub struct Data<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
-    pub(super) data: HashMap<Key, Value>,
+    /// Shared with any [`DataSnapshot`], mutations copy the map if a snapshot
+    /// still holds on to it.
+    pub(super) data: Arc<HashMap<Key, Value>>,
     pub(super) sorted: Permutation,
+    /// Behind a [`RefCell`] so that it can also be used to search through the
+    /// sorted data, see [`Data::values_between`].
+    sorting_fn: RefCell<SortingFn<Value>>,
+    sort_id: Option<SortId>,
+    /// Wether a sorting function other than the default sort by key is set.
+    custom_sort: bool,
+    #[cfg(test)]
+    resort_count: usize,
+    skip_resort: bool,
+    needs_resort: bool,
+    /// When each key was first added, used as the final tie-break when
+    /// sorting so that comparator-equal values keep their insertion order.
+    sequence: HashMap<Key, u64>,
+    next_sequence: u64,
+    /// Behind a [`RefCell`] so that a view can be resorted when it is read,
+    /// see [`Data::sorted_view`].
+    views: RefCell<HashMap<SortId, SortView<Value>>>,
+}
+
+/// Additional sort order of the data, see [`Data::sorted_view`].
+struct SortView<Value> {
     sorting_fn: SortingFn<Value>,
+    sorted: Permutation,
+    /// Wether the data changed since the view was last sorted.
+    stale: bool,
+}
+
+/// The default sort order.
+fn key_order<Key, Value>(a: &Value, b: &Value) -> Ordering
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    a.key().cmp(b.key())
+}
+
+/// Sorts the values by the sorting function, ties are broken by the sequence
+/// the values were inserted in.
+fn sort_permutation<Key, Value>(
+    data: &HashMap<Key, Value>,
+    sequence: &HashMap<Key, u64>,
+    sorting_fn: &mut SortingFn<Value>,
+) -> Permutation
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    permutation::sort_by(data.values().collect_vec(), |a, b| {
+        sorting_fn(*a, *b)
+            .then_with(|| sequence.get(a.key()).cmp(&sequence.get(b.key())))
+    })
 }
 
 impl<Key, Value> Data<Key, Value>
@@ -25,16 +79,32 @@ where
 {
     #[must_use]
     pub(super) fn new() -> Self {
-        let data = HashMap::new();
-        let sorting_fn = |a: &Value, b: &Value| a.key().cmp(b.key());
+        let data = Arc::new(HashMap::new());
         Self {
             data,
-            sorted: permutation::sort_by(Vec::<Value>::new(), sorting_fn),
-            sorting_fn: Box::new(sorting_fn),
+            sorted: permutation::sort_by(Vec::<Value>::new(), key_order::<Key, Value>),
+            sorting_fn: RefCell::new(Box::new(key_order)),
+            sort_id: None,
+            custom_sort: false,
+            #[cfg(test)]
+            resort_count: 0,
+            skip_resort: false,
+            needs_resort: false,
+            sequence: HashMap::new(),
+            next_sequence: 0,
+            views: RefCell::new(HashMap::new()),
         }
     }
     pub(super) fn add_fresh_data(&mut self, data: FreshData<Key, Value>) {
-        self.extend(data.into());
+        if !self.data.is_empty() {
+            self.extend(data.into());
+            return;
+        }
+        // nothing to merge with, so the values can be shared instead of copied
+        let shared = data.into_shared();
+        shared.keys().for_each(|key| self.assign_sequence(key));
+        self.data = shared;
+        self.resort();
     }
     /// Internally decides how the data is mutated depending in the data update
     /// state
@@ -50,7 +120,8 @@ where
             "About to extend this data object with {} values",
             extend.len()
         );
-        self.data.extend(extend);
+        extend.keys().for_each(|key| self.assign_sequence(key));
+        Arc::make_mut(&mut self.data).extend(extend);
         self.resort();
     }
     pub(super) fn insert(&mut self, insert: Vec<Value>) {
@@ -58,7 +129,8 @@ where
             "About to insert {} new values in this data object",
             insert.len()
         );
-        self.data
+        insert.iter().for_each(|value| self.assign_sequence(value.key()));
+        Arc::make_mut(&mut self.data)
             .extend(insert.into_iter().map(|v| (v.key().clone(), v)));
         self.resort();
     }
@@ -68,7 +140,7 @@ where
             update.len()
         );
         for value in update {
-            let Some(old_value) = self.data.get_mut(value.key()) else {
+            let Some(old_value) = Arc::make_mut(&mut self.data).get_mut(value.key()) else {
                 warn!("The value with id [{:?}] tried to be inserted through a update action, which is not correct. Use the insert action for insertion", value.key());
                 continue;
             };
@@ -79,7 +151,8 @@ where
     pub(super) fn delete(&mut self, keys: Vec<Key>) {
         let mut count = 0;
         for key in keys.iter() {
-            if self.data.remove(key).is_some() {
+            self.sequence.remove(key);
+            if Arc::make_mut(&mut self.data).remove(key).is_some() {
                 count += 1;
             }
         }
@@ -87,17 +160,99 @@ where
         self.resort();
     }
     pub(super) fn resort(&mut self) {
-        self.sorted = permutation::sort_by(self.data.values().collect_vec(), |a, b| {
-            (self.sorting_fn)(*a, *b)
-        })
+        if self.skip_resort {
+            self.needs_resort = true;
+            return;
+        }
+        #[cfg(test)]
+        {
+            self.resort_count += 1;
+        }
+        self.sorted = sort_permutation(&self.data, &self.sequence, self.sorting_fn.get_mut());
+        for view in self.views.get_mut().values_mut() {
+            view.stale = true;
+        }
+    }
+    /// Adds a named view with its own sorting function. Unlike the main order
+    /// it is only resorted when it is read after the data changed. Replaces a
+    /// view with the same name.
+    pub(super) fn add_sort_view<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(
+        &mut self,
+        name: SortId,
+        sorting_fn: F,
+    ) {
+        let mut sorting_fn: SortingFn<Value> = Box::new(sorting_fn);
+        let sorted = sort_permutation(&self.data, &self.sequence, &mut sorting_fn);
+        let view = SortView { sorting_fn, sorted, stale: false };
+        self.views.get_mut().insert(name, view);
+    }
+    /// The data sorted by the view with the given name, if it was added.
+    pub fn sorted_view(&self, name: SortId) -> Option<Vec<&Value>> {
+        let mut views = self.views.borrow_mut();
+        let view = views.get_mut(name)?;
+        if std::mem::take(&mut view.stale) {
+            view.sorted = sort_permutation(&self.data, &self.sequence, &mut view.sorting_fn);
+        }
+        Some(view.sorted.apply_slice(self.data.values().collect_vec()))
+    }
+    /// Keys that are already present keep their sequence.
+    fn assign_sequence(&mut self, key: &Key) {
+        if !self.sequence.contains_key(key) {
+            self.sequence.insert(key.clone(), self.next_sequence);
+            self.next_sequence += 1;
+        }
+    }
+    /// Applies all mutations done in `mutate` without resorting in between,
+    /// the data is then resorted once at the end if it needs to be.
+    pub(super) fn with_deferred_resort(&mut self, mutate: impl FnOnce(&mut Self)) {
+        self.skip_resort = true;
+        mutate(self);
+        self.skip_resort = false;
+        if std::mem::take(&mut self.needs_resort) {
+            self.resort();
+        }
     }
     pub(super) fn new_sorting_fn<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(
         &mut self,
         sorting_fn: F,
     ) {
-        self.sorting_fn = Box::new(sorting_fn);
+        self.sorting_fn = RefCell::new(Box::new(sorting_fn));
+        self.sort_id = None;
+        self.custom_sort = true;
         self.resort();
     }
+    /// Goes back to the default sort by key.
+    pub(super) fn reset_sorting_fn(&mut self) {
+        self.new_sorting_fn(key_order);
+        self.custom_sort = false;
+    }
+    /// Same as [`new_sorting_fn`][Data::new_sorting_fn] but does nothing if
+    /// the sorting function with the same `sort_id` is already installed.
+    pub(super) fn new_keyed_sorting_fn<F: FnMut(&Value, &Value) -> Ordering + Send + 'static>(
+        &mut self,
+        sort_id: SortId,
+        sorting_fn: F,
+    ) {
+        if self.sort_id == Some(sort_id) {
+            trace!("Sorting function [{sort_id}] is already installed, skipping resort");
+            return;
+        }
+        self.new_sorting_fn(sorting_fn);
+        self.sort_id = Some(sort_id);
+    }
+    /// Summary of the data, e.g. for a debug panel.
+    pub fn stats(&self) -> DataStats {
+        DataStats {
+            len: self.data.len(),
+            is_sorted_custom: self.custom_sort,
+            capacity: self.data.capacity(),
+        }
+    }
+    /// How many times the data has been resorted so far.
+    #[cfg(test)]
+    pub(crate) fn resort_count(&self) -> usize {
+        self.resort_count
+    }
     pub fn len(&self) -> usize {
         self.data.len()
     }
@@ -106,6 +261,14 @@ where
         self.data.is_empty()
     }
 
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.data.get(key)
+    }
+    /// The value with the given key, or `default` if there is none, e.g. to
+    /// render a placeholder.
+    pub fn get_or<'a>(&'a self, key: &Key, default: &'a Value) -> &'a Value {
+        self.get(key).unwrap_or(default)
+    }
     pub fn keys(&self) -> Vec<&Key> {
         self.data.keys().collect_vec()
     }
@@ -122,7 +285,7 @@ where
         &self.data
     }
     pub fn map_cloned(&self) -> HashMap<Key, Value> {
-        self.data.clone()
+        self.data.as_ref().clone()
     }
     pub fn iter(&self) -> impl Iterator<Item = &Value> + Clone {
         self.data.values()
@@ -138,6 +301,83 @@ where
             .apply_slice(self.data.values().collect_vec())
             .into_iter()
     }
+    /// Value at index `n` of the sorted data, without sorting all values into
+    /// a new vec.
+    pub fn nth_sorted(&self, n: usize) -> Option<&Value> {
+        if n >= self.data.len() {
+            return None;
+        }
+        self.data.values().nth(self.sorted.apply_inv_idx(n))
+    }
+    /// Wether the sorted data is non-decreasing under `cmp`, e.g. to check
+    /// that a resort didn't leave values out of order.
+    pub fn is_sorted_by(&self, cmp: impl Fn(&Value, &Value) -> Ordering) -> bool {
+        self.sorted_iter()
+            .tuple_windows()
+            .all(|(first, second)| cmp(first, second) != Ordering::Greater)
+    }
+    /// The sorted values that match the predicate. Unlike a
+    /// [`Predicate`][crate::query::Predicate] query this runs right away on
+    /// the local data, so the predicate may borrow.
+    pub fn filtered(&self, pred: impl Fn(&Value) -> bool) -> Vec<&Value> {
+        self.sorted_iter().filter(|value| pred(value)).collect_vec()
+    }
+    /// Index of the value with the given key inside of the sorted data.
+    pub fn sorted_position(&self, key: &Key) -> Option<usize> {
+        self.sorted_iter().position(|value| value.key().eq(key))
+    }
+    /// Up to `radius` sorted values before and after the value with the key
+    /// `center`, including that value. Empty if there is no such value.
+    pub fn window(&self, center: &Key, radius: usize) -> Vec<&Value> {
+        let Some(position) = self.sorted_position(center) else {
+            return vec![];
+        };
+        let sorted = self.sorted();
+        let start = position.saturating_sub(radius);
+        let end = (position + radius + 1).min(sorted.len());
+        sorted[start..end].to_vec()
+    }
+    /// How every key moved from the `previous` sorted order to the current one,
+    /// e.g. to animate the transition. Removed keys come first in their
+    /// previous order, then inserted and moved keys in the current order. Keys
+    /// that kept their position are left out.
+    pub fn sorted_diff(&self, previous: &[Key]) -> Vec<Move<Key>> {
+        let current = self.sorted_iter().map(|value| value.key()).collect_vec();
+        let current_positions = current
+            .iter()
+            .enumerate()
+            .map(|(position, key)| (*key, position))
+            .collect::<HashMap<_, _>>();
+        let previous_positions = previous
+            .iter()
+            .enumerate()
+            .map(|(position, key)| (key, position))
+            .collect::<HashMap<_, _>>();
+
+        let removed = previous
+            .iter()
+            .enumerate()
+            .filter(|(_, key)| !current_positions.contains_key(key))
+            .map(|(from, key)| Move::Removed { key: key.clone(), from });
+        let inserted_or_moved = current.iter().enumerate().filter_map(|(to, key)| {
+            match previous_positions.get(key) {
+                None => Some(Move::Inserted { key: (*key).clone(), to }),
+                Some(&from) if from != to => Some(Move::Moved { key: (*key).clone(), from, to }),
+                Some(_) => None,
+            }
+        });
+        removed.chain(inserted_or_moved).collect()
+    }
+    /// The sorted values from `lo` up to and including `hi` according to the
+    /// current sorting function, e.g. all values in a date window. `lo` and
+    /// `hi` don't have to be part of the data.
+    pub fn values_between(&self, lo: &Value, hi: &Value) -> Vec<&Value> {
+        let sorted = self.sorted();
+        let mut sorting_fn = self.sorting_fn.borrow_mut();
+        let start = sorted.partition_point(|value| sorting_fn(value, lo) == Ordering::Less);
+        let end = sorted.partition_point(|value| sorting_fn(value, hi) != Ordering::Greater);
+        sorted[start..end.max(start)].to_vec()
+    }
     /// This has to take the data as sorted otherwise the pagination will make
     /// little sense and is potentially inconsistent
     pub fn page(&self, page: usize, per_page: usize) -> Option<Vec<&Value>> {
@@ -146,6 +386,82 @@ where
             .nth(page)
             .map(|chunk| chunk.to_vec())
     }
+    /// Same as [`page`][Data::page] but for the data sorted by `cmp` instead,
+    /// the stored sort is left as it is. Values that are equal under `cmp`
+    /// keep their current order.
+    pub fn page_sorted_by(
+        &self,
+        cmp: impl Fn(&Value, &Value) -> Ordering,
+        page: usize,
+        per_page: usize,
+    ) -> Option<Vec<&Value>> {
+        let mut sorted = self.sorted();
+        sorted.sort_by(|first, second| cmp(first, second));
+        sorted
+            .chunks(per_page)
+            .nth(page)
+            .map(|chunk| chunk.to_vec())
+    }
+    /// Immutable view of the current data and its order, which won't change
+    /// with any later updates. Cheap as long as the data isn't mutated.
+    pub fn snapshot(&self) -> DataSnapshot<Key, Value> {
+        DataSnapshot {
+            data: self.data.clone(),
+            sorted: self.sorted.clone(),
+        }
+    }
+}
+
+/// Difference of a single key between two sorted orders, see
+/// [`Data::sorted_diff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Move<Key> {
+    Inserted { key: Key, to: usize },
+    Removed { key: Key, from: usize },
+    Moved { key: Key, from: usize, to: usize },
+}
+
+/// Returned by [`Data::stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataStats {
+    pub len: usize,
+    /// Wether a sorting function other than the default sort by key is set.
+    pub is_sorted_custom: bool,
+    /// Capacity of the underlying map.
+    pub capacity: usize,
+}
+
+/// Data and sort order of a [`Data`] at the time [`Data::snapshot`] was called.
+#[derive(Clone)]
+pub struct DataSnapshot<Key, Value> {
+    data: Arc<HashMap<Key, Value>>,
+    sorted: Permutation,
+}
+
+impl<Key, Value> DataSnapshot<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn map(&self) -> &HashMap<Key, Value> {
+        &self.data
+    }
+    pub fn sorted(&self) -> Vec<&Value> {
+        self.sorted.apply_slice(self.data.values().collect_vec())
+    }
+    /// Same as [`Data::page`].
+    pub fn page(&self, page: usize, per_page: usize) -> Option<Vec<&Value>> {
+        self.sorted()
+            .chunks(per_page)
+            .nth(page)
+            .map(|chunk| chunk.to_vec())
+    }
 }
 
 impl<Key, Value> Default for Data<Key, Value>
@@ -14,24 +14,98 @@
 //!     or [`communicators`][DataContainer::communicators]
 //! - Finally don't forget to call [`state_update`][DataContainer::state_update]
 mod comm_info;
+pub mod conflict;
+pub mod errors;
 mod reciver;
 mod resolving_actions;
+pub mod retry;
 pub mod storage;
 mod update_sender;
 
+use std::{
+    collections::{HashMap, HashSet},
+    mem::Discriminant,
+    time::Duration,
+};
+
 use comm_info::CommunicatorInfo;
+use conflict::ConflictStrategy;
+use errors::{ContainerError, ErrorLog};
 use itertools::Itertools;
+use lazy_async_promise::ImmediateValuePromise;
 use reciver::Reciver;
-use resolving_actions::{Action, ResolvedAction, ResolvingAction};
-use storage::Storage;
-use tokio::sync::mpsc;
-use tracing::{debug, info, trace};
-use update_sender::UpdateSender;
+use resolving_actions::{Action, QueryTicket, ResolvedAction, ResolvingAction, RunningStream};
+use retry::RetryPolicy;
+use storage::{InitError, Storage, StorageCapabilities};
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::time::Instant;
+use tracing::{debug, info, trace, warn};
+use update_sender::{UpdateSender, INITIAL_CHANGE_CAPACITY};
 use uuid::Uuid;
 
-use crate::{change::DataChange, query::FreshData};
+use crate::{
+    change::{ChangeError, ChangeResponse, DataChange},
+    query::{DataQuery, FreshData, QueryError, QueryResponse, QueryResult, QueryType},
+};
+
+use super::{
+    communicator::{Communicator, AWAIT_POLL_INTERVAL},
+    utils::DrainIf,
+    KeyBounds, ValueBounds,
+};
+
+/// Control signals sent to every communicator with
+/// [`broadcast_signal`][DataContainer::broadcast_signal], independent of any
+/// change to the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    /// The data was reloaded, e.g. to show a banner.
+    Reloaded,
+    /// The data was cleared.
+    Cleared,
+}
 
-use super::{communicator::Communicator, utils::DrainIf, KeyBounds, ValueBounds};
+/// What a single [`state_update`][DataContainer::state_update] did, see
+/// [`set_tick_hook`][DataContainer::set_tick_hook].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StateUpdateReport {
+    /// Finished changes that were sent to the interested communicators.
+    pub changes: usize,
+    /// Finished queries whose data was returned to the communicator.
+    pub queries: usize,
+    /// Finished changes and queries that failed.
+    pub errors: usize,
+    /// New changes and queries that were recived from communicators.
+    pub new_actions: usize,
+}
+
+/// Diagnostics of the whole container, see
+/// [`debug_report`][DataContainer::debug_report].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContainerDebugReport {
+    /// Every registered communicator, ordered by uuid.
+    pub communicators: Vec<CommunicatorDebugInfo>,
+    /// Changes and queries the storage is still working on.
+    pub running_actions: usize,
+    /// Streamed queries that haven't finished yet.
+    pub running_streams: usize,
+    /// Changes and fresh data that are still being sent to communicators.
+    pub pending_sends: usize,
+}
+
+/// What the container knows about a single communicator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommunicatorDebugInfo {
+    pub uuid: Uuid,
+    /// Number of keys the communicator is interested in.
+    pub keys: usize,
+    /// Display of the last query the communicator performed.
+    pub last_query: Option<String>,
+    /// Changes held back because the communicator's channel is full.
+    pub held_back_changes: usize,
+}
+
+type TickHook = Box<dyn FnMut(&StateUpdateReport) + Send + 'static>;
 
 pub struct DataContainer<Key, Value, Writer>
 where
@@ -45,6 +119,29 @@ where
     storage: Writer,
     comm_info: CommunicatorInfo<Key, Value>,
     running_actions: Vec<ResolvingAction<Key, Value>>,
+    running_streams: Vec<RunningStream<Key, Value>>,
+    conflict_strategy: ConflictStrategy<Value>,
+    errors: ErrorLog,
+    /// Error of the failed [`Storage::migrate`], every action is answered
+    /// with it instead of reaching the storage.
+    migration_error: Option<ChangeError>,
+    max_query_result_size: Option<usize>,
+    external_changes: Option<mpsc::Receiver<DataChange<Key, Value>>>,
+    /// The stream of external changes ended and the storage didn't return a
+    /// new one yet.
+    external_changes_lost: bool,
+    suppress_stale_queries: bool,
+    next_query_sequence: u64,
+    /// Sequence of the latest query of every kind each communicator sent.
+    latest_queries: HashMap<(Uuid, Discriminant<QueryType<Key, Value>>), u64>,
+    tick_hook: Option<TickHook>,
+    prune_idle_communicators: bool,
+    cache_all_queries: bool,
+    /// Result of the last [`QueryType::All`] if nothing changed since.
+    all_cache: Option<FreshData<Key, Value>>,
+    /// Only queries with at least this sequence started after the last change
+    /// and may fill the cache.
+    cache_valid_from: u64,
 }
 
 impl<Key, Value, Writer> DataContainer<Key, Value, Writer>
@@ -53,19 +150,103 @@ where
     Value: ValueBounds<Key>,
     Writer: Storage<Key, Value>,
 {
+    /// Initializes the storage and runs its [`Storage::migrate`]. If the
+    /// migration fails its error is logged, see
+    /// [`drain_errors`][DataContainer::drain_errors], and every change and
+    /// query is answered with it without reaching the storage.
     pub fn init(
         storage_args: Writer::InitArgs,
     ) -> impl std::future::Future<Output = Self> + Send + 'static {
         let storage_future = Writer::init(storage_args);
         async move {
-            Self {
-                uuid: Uuid::new_v4(),
-                reciver: Reciver::default(),
-                update_sender: UpdateSender::default(),
-                comm_info: CommunicatorInfo::default(),
-                storage: storage_future.await,
-                running_actions: Vec::default(),
+            let mut storage = storage_future.await;
+            let migration = storage.migrate().await;
+            let mut container = Self::from_storage(storage);
+            if let Err(err) = migration {
+                warn!(
+                    msg = format!("Migrating the storage failed with [{err}]."),
+                    cont = container.uuid.to_string()
+                );
+                container.errors.push(ContainerError::Change(err.clone()));
+                container.migration_error = Some(err);
             }
+            container
+        }
+    }
+
+    /// Same as [`init`][DataContainer::init] but initializes the storage with
+    /// [`Storage::try_init`], retrying according to the policy. Returns the
+    /// error of the last attempt if the storage couldn't be initialized. A
+    /// failed [`Storage::migrate`] is returned as an error right away.
+    pub fn init_with_retry(
+        storage_args: Writer::InitArgs,
+        policy: RetryPolicy,
+    ) -> impl std::future::Future<Output = Result<Self, InitError>> + Send + 'static
+    where
+        Writer::InitArgs: Clone + Send + 'static,
+    {
+        async move {
+            let deadline = Instant::now() + policy.timeout;
+            let mut backoff = policy.initial_backoff;
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let error = match tokio::time::timeout_at(
+                    deadline,
+                    Writer::try_init(storage_args.clone()),
+                )
+                .await
+                {
+                    Ok(Ok(mut storage)) => {
+                        return match storage.migrate().await {
+                            Ok(()) => Ok(Self::from_storage(storage)),
+                            Err(err) => Err(InitError(format!("Migration failed with [{err}]"))),
+                        };
+                    }
+                    Ok(Err(error)) => error,
+                    Err(_) => InitError(format!("Timed out during attempt {attempt}")),
+                };
+                if attempt >= policy.max_attempts || Instant::now() + backoff >= deadline {
+                    warn!(msg = format!(
+                        "Giving up initializing the storage after {attempt} attempts, \
+                        last error was [{error}]"
+                    ));
+                    return Err(error);
+                }
+                debug!(msg = format!(
+                    "Attempt {attempt} to initialize the storage failed with [{error}], \
+                    retrying in {backoff:?}"
+                ));
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(policy.max_backoff);
+            }
+        }
+    }
+
+    fn from_storage(mut storage: Writer) -> Self {
+        let external_changes = storage.external_change_stream();
+        Self {
+            uuid: Uuid::new_v4(),
+            reciver: Reciver::default(),
+            update_sender: UpdateSender::default(),
+            comm_info: CommunicatorInfo::default(),
+            storage,
+            running_actions: Vec::default(),
+            running_streams: Vec::default(),
+            conflict_strategy: ConflictStrategy::default(),
+            errors: ErrorLog::default(),
+            migration_error: None,
+            max_query_result_size: None,
+            external_changes,
+            external_changes_lost: false,
+            suppress_stale_queries: false,
+            next_query_sequence: 0,
+            latest_queries: HashMap::new(),
+            tick_hook: None,
+            prune_idle_communicators: false,
+            cache_all_queries: false,
+            all_cache: None,
+            cache_valid_from: 0,
         }
     }
 
@@ -77,8 +258,20 @@ where
     ///     - Query: return data to the respective communicator
     /// - Recieve any new Actions
     pub fn state_update(&mut self) {
+        self.state_update_budgeted(usize::MAX);
+    }
+
+    /// Same as [`state_update`][DataContainer::state_update] but resolves at
+    /// most `max_actions` finished actions and recives at most `max_actions`
+    /// new ones. Whatever is left over is handled by the following calls.
+    pub fn state_update_budgeted(&mut self, max_actions: usize) {
+        if self.prune_idle_communicators {
+            self.prune_idle_communicators();
+        }
         self.update_sender.state_update();
-        self.resolve_finished_actions()
+        self.recive_external_changes();
+        let mut report = StateUpdateReport::default();
+        self.resolve_finished_actions(max_actions)
             .into_iter()
             .for_each(|action| match action {
                 ResolvedAction::Change(change) => {
@@ -86,17 +279,51 @@ where
                         msg = format!("Finished change action, updating communicators."),
                         cont = self.uuid.to_string()
                     );
+                    report.changes += 1;
                     self.update_communicators(&change)
                 }
-                ResolvedAction::Query(query, uuid) => {
+                ResolvedAction::Rekey { old, value } => {
+                    trace!(
+                        msg = format!("Finished rekey action, updating communicators."),
+                        cont = self.uuid.to_string()
+                    );
+                    report.changes += 1;
+                    self.rekey_communicators(old, value)
+                }
+                ResolvedAction::Query(query, uuid, ticket) => {
+                    self.fill_all_cache(&query, &ticket);
+                    if self.is_stale(&uuid, &ticket) {
+                        debug!(
+                            msg = format!("Query of [{uuid}] was superseded, dropping its result."),
+                            cont = self.uuid.to_string()
+                        );
+                        return;
+                    }
                     trace!(
                         msg = format!("Finished query action, returning result."),
                         cont = self.uuid.to_string()
                     );
-                    self.return_query(uuid, query)
+                    report.queries += 1;
+                    if ticket.replaces {
+                        self.drop_missing(&uuid, &query);
+                    }
+                    self.return_query(uuid, query.with_query_id(ticket.query_id))
+                }
+                ResolvedAction::Error(error) => {
+                    debug!(
+                        msg = format!("Finished action with error [{error:?}], logging it."),
+                        cont = self.uuid.to_string()
+                    );
+                    report.errors += 1;
+                    self.errors.push(error)
                 }
+                ResolvedAction::Pending(action) => self.running_actions.push(action),
             });
-        self.recive_new_actions();
+        self.recive_stream_chunks(&mut report);
+        report.new_actions = self.recive_new_actions(max_actions);
+        if let Some(tick_hook) = self.tick_hook.as_mut() {
+            tick_hook(&report);
+        }
     }
 
     pub fn communicator(&mut self) -> Communicator<Key, Value> {
@@ -107,34 +334,327 @@ where
             cont = self.uuid.to_string()
         );
 
-        let (change_sender, query_sender) = self.reciver.senders();
+        let (change_sender, query_sender, priority_query_sender) = self.reciver.senders();
 
         // WARNING: if a page is not visited in a while, these could easily fill up
-        let (change_data_sender, change_data_reciver) = mpsc::channel(20);
+        let (change_data_sender, change_data_reciver) = mpsc::channel(INITIAL_CHANGE_CAPACITY);
+        let (grown_reciver_sender, grown_reciver_reciver) = mpsc::unbounded_channel();
         let (fresh_data_sender, fresh_data_reciver) = mpsc::channel(20);
+        let (signal_sender, signal_reciver) = mpsc::unbounded_channel();
 
-        self.update_sender
-            .register_senders(&new_uuid, change_data_sender, fresh_data_sender);
+        self.update_sender.register_senders(
+            &new_uuid,
+            change_data_sender,
+            grown_reciver_sender,
+            fresh_data_sender,
+            signal_sender,
+        );
         self.comm_info.register_comm(&new_uuid);
 
         Communicator::new(
             new_uuid,
             change_sender,
             query_sender,
+            priority_query_sender,
             change_data_reciver,
+            grown_reciver_reciver,
             fresh_data_reciver,
+            signal_reciver,
         )
     }
 
+    /// Sets how inserts for the same key recived in the same
+    /// [`state_update`][DataContainer::state_update] are resolved.
+    pub fn set_conflict_strategy(&mut self, strategy: ConflictStrategy<Value>) -> &mut Self {
+        self.conflict_strategy = strategy;
+        self
+    }
+
+    /// Sets how many of the most recent errors are kept until they are
+    /// drained with [`drain_errors`][DataContainer::drain_errors].
+    pub fn set_error_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.errors.set_capacity(capacity);
+        self
+    }
+
+    /// Queries returning more than `max` values resolve with
+    /// [`QueryError::ResultTooLarge`][crate::query::QueryError::ResultTooLarge]
+    /// instead of sending the values to the communicator. `None` disables the
+    /// check, which is the default.
+    pub fn set_max_query_result_size(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_query_result_size = max;
+        self
+    }
+
+    /// The channel sending changes to a communicator grows when it repeatedly
+    /// is full, since the communicator isn't updated often enough. Once it
+    /// reaches `max`, further changes are merged and held back until the
+    /// communicator made room again. Defaults to `160`.
+    pub fn set_max_change_capacity(&mut self, max: usize) -> &mut Self {
+        self.update_sender.set_max_change_capacity(max);
+        self
+    }
+
+    /// Current capacity of the channel sending changes to the communicator,
+    /// see [`set_max_change_capacity`][DataContainer::set_max_change_capacity].
+    pub fn change_capacity(&self, uuid: &Uuid) -> Option<usize> {
+        self.update_sender.change_capacity(uuid)
+    }
+
+    /// When a communicator sends a query while an older query of the same kind
+    /// from it is still running, the result of the older query is not sent to
+    /// the communicator anymore, e.g. for rapid-fire queries while typing. The
+    /// older query itself still resolves. Disabled by default.
+    pub fn set_suppress_stale_queries(&mut self, suppress: bool) -> &mut Self {
+        self.suppress_stale_queries = suppress;
+        self
+    }
+
+    /// Keeps the result of the last [`QueryType::All`] until the next change,
+    /// so that further `All` queries in between don't reach the storage. This
+    /// holds a copy of all the data in memory. Disabled by default.
+    pub fn set_cache_all_queries(&mut self, cache: bool) -> &mut Self {
+        self.cache_all_queries = cache;
+        if !cache {
+            self.all_cache = None;
+        }
+        self
+    }
+
+    /// Sets a callback that is called at the end of every
+    /// [`state_update`][DataContainer::state_update] with what it did, e.g. to
+    /// collect metrics.
+    pub fn set_tick_hook(
+        &mut self,
+        f: impl FnMut(&StateUpdateReport) + Send + 'static,
+    ) -> &mut Self {
+        self.tick_hook = Some(Box::new(f));
+        self
+    }
+
+    /// Runs [`prune_idle_communicators`][DataContainer::prune_idle_communicators]
+    /// at the start of every [`state_update`][DataContainer::state_update].
+    /// Disabled by default.
+    pub fn set_prune_idle_communicators(&mut self, prune: bool) -> &mut Self {
+        self.prune_idle_communicators = prune;
+        self
+    }
+
+    /// Forgets every communicator that was dropped, e.g. because the panel
+    /// showing it was closed. Returns how many were removed.
+    pub fn prune_idle_communicators(&mut self) -> usize {
+        let closed = self.update_sender.closed_communicators();
+        for uuid in &closed {
+            self.update_sender.unregister_senders(uuid);
+            self.comm_info.unregister_comm(uuid);
+            self.latest_queries.retain(|(origin, _), _| origin != uuid);
+        }
+        if !closed.is_empty() {
+            info!(
+                msg = format!("Pruned {} dropped communicators.", closed.len()),
+                cont = self.uuid.to_string()
+            );
+        }
+        closed.len()
+    }
+
+    /// Direct access to the underlying storage.
+    pub fn storage(&self) -> &Writer {
+        &self.storage
+    }
+
+    /// Mutable access to the underlying storage, e.g. for migrations.
+    ///
+    /// Anything changed through this bypasses the communicators, they won't
+    /// know about it until they query again or the change is sent to them
+    /// with [`force_broadcast`][DataContainer::force_broadcast].
+    pub fn storage_mut(&mut self) -> &mut Writer {
+        &mut self.storage
+    }
+
+    /// Sends the change to every interested communicator as if the storage
+    /// had just made it, without handing it to the storage. Meant to catch
+    /// the communicators up after changing the storage directly.
+    pub fn force_broadcast(&mut self, change: DataChange<Key, Value>) {
+        debug!(
+            msg = format!("Forcing broadcast of a change to {} keys.", change.len()),
+            cont = self.uuid.to_string()
+        );
+        self.update_communicators(&change);
+    }
+
+    /// Advanced features the underlying storage supports.
+    pub fn storage_capabilities(&self) -> StorageCapabilities {
+        self.storage.capabilities()
+    }
+
+    /// Sends the signal to every communicator, where it can be taken with
+    /// [`take_signals`][Communicator::take_signals].
+    pub fn broadcast_signal(&mut self, signal: Signal) {
+        self.update_sender.send_signal(&self.uuid, signal);
+    }
+
+    /// Returns and clears the most recent errors the storage returned for any
+    /// change or query, regardless of which communicator sent it.
+    pub fn drain_errors(&mut self) -> Vec<ContainerError> {
+        self.errors.drain()
+    }
+
     pub fn communicators<const N: usize>(&mut self) -> [Communicator<Key, Value>; N] {
         std::array::from_fn(|_| self.communicator())
     }
 
+    /// Returns the keys and the last query the container thinks the communicator
+    /// is interested in. Only meant for debugging why data does or doesn't reach
+    /// a communicator.
+    pub fn communicator_interest(&self, uuid: &Uuid) -> Option<(HashSet<Key>, Option<String>)> {
+        self.comm_info.interest(uuid)
+    }
+
+    /// Keeps updating until nothing is left that could still reach the
+    /// communicator: no running change or query of it, no held back changes
+    /// and no send still in flight. Once this resolved a single
+    /// [`state_update`][Communicator::state_update] of the communicator
+    /// recives everything, e.g. to synchronize tests without sleeping.
+    pub async fn await_communicator_synced(&mut self, uuid: Uuid) {
+        loop {
+            self.state_update();
+            if self.is_synced(&uuid) {
+                return;
+            }
+            tokio::time::sleep(AWAIT_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Same as [`state_update`][DataContainer::state_update], but if there is
+    /// no work left afterwards this waits until a communicator sends a change
+    /// or query, at most for `max_wait`. Lets a service call this in a loop
+    /// without spinning while idle. Changes of the storage's
+    /// [`external_change_stream`][Storage::external_change_stream] don't end
+    /// the wait early.
+    ///
+    /// While work is still running this only waits for a short moment, at
+    /// most `max_wait`, so that the running work can make progress before the
+    /// next state update, even on a single threaded runtime.
+    pub async fn state_update_or_wait(&mut self, max_wait: Duration) {
+        self.state_update();
+        if self.has_running_work() {
+            tokio::time::sleep(max_wait.min(AWAIT_POLL_INTERVAL)).await;
+            return;
+        }
+        if tokio::time::timeout(max_wait, self.reciver.wait_for_action())
+            .await
+            .is_ok()
+        {
+            self.state_update();
+        }
+    }
+
+    /// Wether any action, stream or send is still in flight and would be
+    /// handled by a following state update.
+    fn has_running_work(&self) -> bool {
+        !self.running_actions.is_empty()
+            || !self.running_streams.is_empty()
+            || self.update_sender.pending_sends() > 0
+    }
+
+    fn is_synced(&self, uuid: &Uuid) -> bool {
+        !self.running_actions.iter().any(|action| action.might_reach(uuid))
+            && !self.running_streams.iter().any(|stream| stream.origin_uuid == *uuid)
+            && self.update_sender.held_back_changes(uuid) == 0
+            && self.update_sender.pending_sends() == 0
+    }
+
+    /// One-shot report of every communicator's interest and of the work still
+    /// in flight, e.g. for a diagnostics dashboard.
+    pub fn debug_report(&self) -> ContainerDebugReport {
+        let communicators = self
+            .comm_info
+            .summaries()
+            .into_iter()
+            .map(|(uuid, keys, last_query)| CommunicatorDebugInfo {
+                uuid,
+                keys,
+                last_query,
+                held_back_changes: self.update_sender.held_back_changes(&uuid),
+            })
+            .sorted_by_key(|info| info.uuid)
+            .collect_vec();
+        ContainerDebugReport {
+            communicators,
+            running_actions: self.running_actions.len(),
+            running_streams: self.running_streams.len(),
+            pending_sends: self.update_sender.pending_sends(),
+        }
+    }
+
     /// Takes a fresh [`DataChange`] which is then cloned and fitted to every
     /// interested communicator and finally sent to each communicator.
+    /// Communicators that were dropped in the meantime are skipped, the
+    /// change itself was still written to the storage.
     fn update_communicators(&mut self, update: &DataChange<Key, Value>) {
-        let keys = update.value_keys();
         let communicators = self.comm_info.get_interested_comm(update);
+        self.broadcast(update.value_keys().len(), communicators);
+        if let DataChange::Insert(_) = update {
+            self.requery_latest();
+        }
+    }
+
+    /// Communicators whose last query was a [`QueryType::Latest`] repeat it,
+    /// since whether the inserted values are among the latest depends on all
+    /// other values.
+    fn requery_latest(&mut self) {
+        let queries = self
+            .comm_info
+            .last_queries()
+            .into_iter()
+            .filter(|(uuid, query_type)| {
+                matches!(query_type, QueryType::Latest { .. })
+                    && !self.update_sender.is_closed(uuid)
+            })
+            .collect_vec();
+        for (uuid, query_type) in queries {
+            self.requery(uuid, query_type);
+        }
+    }
+
+    /// Runs the query again for the communicator. The result of a repeated
+    /// [`QueryType::Latest`] replaces what the communicator holds, since
+    /// values drop out of it when newer ones are inserted.
+    fn requery(&mut self, uuid: Uuid, query_type: QueryType<Key, Value>) {
+        let mut ticket = self.next_query_ticket(&uuid, &query_type, None);
+        ticket.replaces = matches!(query_type, QueryType::Latest { .. });
+        let promise = self.storage.handle_query(query_type);
+        self.running_actions.push(ResolvingAction::Query(promise, uuid, None, None, ticket));
+    }
+
+    /// Sends the delete of `old` to every communicator holding it. Those are
+    /// also sent the moved value, like any communicator whose last query
+    /// matches it.
+    fn rekey_communicators(&mut self, old: Key, value: Value) {
+        let delete = DataChange::Delete(vec![old]);
+        let holders = self.comm_info.get_interested_comm(&delete);
+        let insert = DataChange::Insert(vec![value]);
+        let mut inserts = self.comm_info.get_interested_comm(&insert);
+        for (holder, _) in &holders {
+            if !inserts.iter().any(|(target, _)| target == holder) {
+                inserts.push((*holder, insert.clone()));
+            }
+        }
+        self.broadcast(1, holders);
+        self.broadcast(1, inserts);
+    }
+
+    /// Sends each communicator its part of a change that modified
+    /// `changed_keys` keys.
+    fn broadcast(
+        &mut self,
+        changed_keys: usize,
+        mut communicators: Vec<(Uuid, DataChange<Key, Value>)>,
+    ) {
+        self.all_cache = None;
+        self.cache_valid_from = self.next_query_sequence;
+        communicators.retain(|(target, _)| !self.update_sender.is_closed(target));
         communicators.iter().for_each(|(target, change)| {
             self.comm_info.update_info_from_change(target, change);
         });
@@ -142,7 +662,7 @@ where
         debug!(
             msg = format!(
                 "Recived data update will modify {} keys and go to {} communicators",
-                keys.len(),
+                changed_keys,
                 communicators.len()
             ),
             cont = self.uuid.to_string()
@@ -151,6 +671,21 @@ where
         self.update_sender.send_change(&self.uuid, communicators);
     }
 
+    /// Deletes the values the communicator holds that aren't part of the
+    /// `query` result anymore.
+    fn drop_missing(&mut self, communicator: &Uuid, query: &FreshData<Key, Value>) {
+        let Some((held, _)) = self.comm_info.interest(communicator) else {
+            return;
+        };
+        let missing = held.into_iter().filter(|key| !query.contains_key(key)).collect_vec();
+        if missing.is_empty() {
+            return;
+        }
+        let delete = DataChange::Delete(missing);
+        self.comm_info.update_info_from_change(communicator, &delete);
+        self.update_sender.send_change(&self.uuid, vec![(*communicator, delete)]);
+    }
+
     /// Takes the [`FreshData`] object and retrives the keys of it to update which
     /// values the communicator is interested in and then finally sends the object
     /// to the communicator.
@@ -170,13 +705,183 @@ where
             .send_fresh_data(&self.uuid, values, &communicator);
     }
 
+    /// Relays all changes from the storage's stream of external changes. If the
+    /// stream ended the storage is asked to resubscribe.
+    fn recive_external_changes(&mut self) {
+        if self.external_changes_lost {
+            self.resubscribe();
+        }
+        let Some(reciver) = self.external_changes.as_mut() else {
+            return;
+        };
+        let mut changes = vec![];
+        let disconnected = loop {
+            match reciver.try_recv() {
+                Ok(change) => changes.push(change),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+        changes
+            .iter()
+            .for_each(|change| self.update_communicators(change));
+        if disconnected {
+            warn!(
+                msg = format!("Stream of external changes ended, resubscribing."),
+                cont = self.uuid.to_string()
+            );
+            self.external_changes = None;
+            self.external_changes_lost = true;
+            self.resubscribe();
+        }
+    }
+
+    /// Replaces the lost stream of external changes, if the storage returns a
+    /// new one every communicator repeats its last query so that changes made
+    /// while there was no stream aren't missed.
+    fn resubscribe(&mut self) {
+        let Some(reciver) = self.storage.resubscribe() else {
+            return;
+        };
+        self.external_changes = Some(reciver);
+        self.external_changes_lost = false;
+
+        let queries = self.comm_info.last_queries();
+        info!(
+            msg = format!("Resubscribed to external changes, repeating {} queries.", queries.len()),
+            cont = self.uuid.to_string()
+        );
+        for (uuid, query_type) in queries {
+            self.requery(uuid, query_type);
+        }
+    }
+
+    /// Sends every chunk the storage returned for a streamed query to the
+    /// communicator. Once the storage is done or returned an error the
+    /// communicator is sent the end of the stream.
+    fn recive_stream_chunks(&mut self, report: &mut StateUpdateReport) {
+        let mut finished = vec![];
+        for (index, stream) in self.running_streams.iter_mut().enumerate() {
+            let result = loop {
+                match stream.reciver.try_recv() {
+                    Ok(QueryResponse::Ok(chunk)) => {
+                        report.queries += 1;
+                        // NOTE: only the first chunk replaces what the communicator
+                        // held before, later ones add to it
+                        if stream.fetched == 0 {
+                            self.comm_info
+                                .update_info_from_query(&stream.origin_uuid, &chunk);
+                        } else {
+                            self.comm_info
+                                .extend_info_from_chunk(&stream.origin_uuid, &chunk);
+                        }
+                        stream.fetched += chunk.len();
+                        self.update_sender.send_fresh_data(
+                            &self.uuid,
+                            chunk.with_query_id(stream.query_id),
+                            &stream.origin_uuid,
+                        );
+                    }
+                    Ok(QueryResponse::Groups(groups)) => break Some(QueryResult::Groups(groups)),
+                    Ok(QueryResponse::Err(err)) => {
+                        report.errors += 1;
+                        self.errors.push(ContainerError::Query(err.clone()));
+                        break Some(QueryResult::Error(err));
+                    }
+                    Err(TryRecvError::Empty) => break None,
+                    Err(TryRecvError::Disconnected) => break Some(QueryResult::Success),
+                }
+            };
+            if let Some(result) = result {
+                finished.push((index, result));
+            }
+        }
+        for (index, result) in finished.into_iter().rev() {
+            let stream = self.running_streams.remove(index);
+            debug!(
+                msg = format!(
+                    "Streamed query of [{}] ended after {} values.",
+                    stream.origin_uuid, stream.fetched
+                ),
+                cont = self.uuid.to_string()
+            );
+            self.update_sender.send_fresh_data(
+                &self.uuid,
+                FreshData::stream_end(stream.query_id, stream.fetched),
+                &stream.origin_uuid,
+            );
+            let _ = stream.response_sender.send(result);
+        }
+    }
+
+    /// Hands a streamed query to the storage.
+    fn start_stream(&mut self, query: DataQuery<Key, Value>) {
+        self.comm_info.update_query(&query);
+        let reciver = self.storage.query_streamed(query.query_type);
+        self.running_streams.push(RunningStream {
+            reciver,
+            origin_uuid: query.origin_uuid,
+            query_id: query.query_id,
+            response_sender: query.response_sender,
+            fetched: 0,
+        });
+    }
+
+    /// Marks the query as the latest of its kind from the communicator.
+    fn next_query_ticket(
+        &mut self,
+        origin: &Uuid,
+        query_type: &QueryType<Key, Value>,
+        query_id: Option<u64>,
+    ) -> QueryTicket<Key, Value> {
+        let ticket = QueryTicket {
+            kind: std::mem::discriminant(query_type),
+            sequence: self.next_query_sequence,
+            query_id,
+            replaces: false,
+        };
+        self.next_query_sequence += 1;
+        self.latest_queries
+            .insert((*origin, ticket.kind), ticket.sequence);
+        ticket
+    }
+
+    /// Caches the result of an [`QueryType::All`] that was sent after the last
+    /// change, see [`set_cache_all_queries`][DataContainer::set_cache_all_queries].
+    fn fill_all_cache(
+        &mut self,
+        query: &FreshData<Key, Value>,
+        ticket: &QueryTicket<Key, Value>,
+    ) {
+        if self.cache_all_queries
+            && ticket.kind == std::mem::discriminant(&QueryType::All)
+            && ticket.sequence >= self.cache_valid_from
+        {
+            self.all_cache = Some(query.clone());
+        }
+    }
+
+    fn is_stale(&self, origin: &Uuid, ticket: &QueryTicket<Key, Value>) -> bool {
+        self.suppress_stale_queries
+            && self.latest_queries.get(&(*origin, ticket.kind)) != Some(&ticket.sequence)
+    }
 
-    fn resolve_finished_actions(&mut self) -> Vec<ResolvedAction<Key, Value>> {
+    fn resolve_finished_actions(&mut self, max_actions: usize) -> Vec<ResolvedAction<Key, Value>> {
+        let mut budget = max_actions;
         // NOTE: the `is_done` function here will poll the interal state of the
         // promise. I think this is nessesary since otherwise no work will be
         // done on the function
         self.running_actions
-            .drain_if_iter(|e| e.poll_and_finished())
+            .drain_if_iter(|e| {
+                if budget == 0 {
+                    return false;
+                }
+                let finished = e.poll_and_finished();
+                if finished {
+                    budget -= 1;
+                }
+                finished
+            })
             .filter_map(|resolving_action| {
                 trace!(
                     msg = format!(
@@ -185,7 +890,7 @@ where
                     ),
                     cont = self.uuid.to_string()
                 );
-                resolving_action.resolve(&self.uuid)
+                resolving_action.resolve(&self.uuid, &mut self.storage, self.max_query_result_size)
             })
             .collect_vec()
     }
@@ -193,30 +898,60 @@ where
     /// Revives any new actions from the Revicers and then calls the respective
     /// methods on the [`Storage`] implementation. The returned futures are then
     /// placed in a vector to be retrived once done.
-    fn recive_new_actions(&mut self) {
-        let new_action = self
-            .reciver
-            .recive_new(&self.uuid)
+    /// Returns how many new actions were recived.
+    fn recive_new_actions(&mut self, max_actions: usize) -> usize {
+        let mut new_actions = self.reciver.recive_new(&self.uuid, max_actions);
+        self.conflict_strategy.resolve_inserts(&mut new_actions);
+
+        let recived = new_actions.len();
+        let new_action = new_actions
             .into_iter()
-            .map(|action| {
+            .filter_map(|action| {
                 debug!(
                     msg = format!("Recived new [{action}] action to work on."),
                     cont = self.uuid.to_string()
                 );
-                match action {
+                if let Some(err) = self.migration_error.clone() {
+                    return Some(self.refuse(action, err));
+                }
+                Some(match action {
                     Action::Change(change) => ResolvingAction::Change(
                         self.storage.handle_change(change.action),
                         change.reponse_sender,
                     ),
+                    Action::Query(query) if query.streamed => {
+                        self.start_stream(query);
+                        return None;
+                    }
                     Action::Query(query) => {
                         self.comm_info.update_query(&query);
+                        let ticket = self.next_query_ticket(
+                            &query.origin_uuid,
+                            &query.query_type,
+                            query.query_id,
+                        );
+                        let promise = match (&query.query_type, &self.all_cache) {
+                            (QueryType::All, Some(cached)) => {
+                                trace!(
+                                    msg = format!("Answering query with the cached result."),
+                                    cont = self.uuid.to_string()
+                                );
+                                let cached = cached.clone();
+                                ImmediateValuePromise::new(async move {
+                                    Ok(QueryResponse::Ok(cached))
+                                })
+                            }
+                            _ => self.storage.handle_query(query.query_type),
+                        };
                         ResolvingAction::Query(
-                            self.storage.handle_query(query.query_type),
+                            promise,
                             query.origin_uuid,
-                            query.response_sender,
+                            Some(query.response_sender),
+                            query.fresh_data_sender,
+                            ticket,
                         )
                     }
-                }
+                })
             })
             .collect::<Vec<_>>();
 
@@ -228,5 +963,33 @@ where
         }
 
         self.running_actions.extend(new_action);
+        recived
+    }
+
+    /// Answers the action with the error of the failed migration, so that the
+    /// storage is never used before it was migrated.
+    fn refuse(
+        &mut self,
+        action: Action<Key, Value>,
+        err: ChangeError,
+    ) -> ResolvingAction<Key, Value> {
+        match action {
+            Action::Change(change) => ResolvingAction::Change(
+                ImmediateValuePromise::new(async move { Ok(ChangeResponse::Err(err)) }),
+                change.reponse_sender,
+            ),
+            Action::Query(query) => {
+                let ticket =
+                    self.next_query_ticket(&query.origin_uuid, &query.query_type, query.query_id);
+                let unmigrated = QueryError::Backend(format!("Migration failed with [{err}]"));
+                ResolvingAction::Query(
+                    ImmediateValuePromise::new(async move { Ok(QueryResponse::Err(unmigrated)) }),
+                    query.origin_uuid,
+                    Some(query.response_sender),
+                    query.fresh_data_sender,
+                    ticket,
+                )
+            }
+        }
     }
 }
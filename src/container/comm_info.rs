@@ -35,10 +35,48 @@ where
     pub fn register_comm(&mut self, comm_uuid: &Uuid) {
         self.comm_to_info.insert(*comm_uuid, Info::default());
     }
+    pub fn unregister_comm(&mut self, comm_uuid: &Uuid) {
+        self.comm_to_info.remove(comm_uuid);
+    }
+    /// Copy of the keys and the display of the last query for a communicator.
+    pub fn interest(&self, comm_uuid: &Uuid) -> Option<(HashSet<Key>, Option<String>)> {
+        self.comm_to_info.get(comm_uuid).map(|info| {
+            (
+                info.value_keys.clone(),
+                info.last_query.as_ref().map(|query| format!("{query}")),
+            )
+        })
+    }
+    /// Every communicator with the number of keys it is interested in and the
+    /// display of its last query.
+    pub fn summaries(&self) -> Vec<(Uuid, usize, Option<String>)> {
+        self.comm_to_info
+            .iter()
+            .map(|(comm, info)| {
+                let last_query = info.last_query.as_ref().map(|query| format!("{query}"));
+                (*comm, info.value_keys.len(), last_query)
+            })
+            .collect_vec()
+    }
+    /// The last query of every communicator that performed one.
+    pub fn last_queries(&self) -> Vec<(Uuid, QueryType<Key, Value>)> {
+        self.comm_to_info
+            .iter()
+            .filter_map(|(comm, info)| Some((*comm, info.last_query.clone()?)))
+            .collect_vec()
+    }
+    /// Communicators that were unregistered in the meantime, e.g. because
+    /// they were pruned with a query still in flight, are skipped here and in
+    /// the other `update_` functions.
     pub fn update_query(&mut self, query: &DataQuery<Key, Value>) {
         let Some(info) = self.comm_to_info.get_mut(&query.origin_uuid) else {
-            unreachable!();
+            return;
         };
+        // NOTE: a group count doesn't hand any values to the communicator, so
+        // it says nothing about which inserts the communicator is interested in
+        if let QueryType::GroupCount { .. } = query.query_type {
+            return;
+        }
         info.last_query = Some(query.query_type.clone());
     }
 
@@ -48,6 +86,8 @@ where
     /// If the change is only an update or a delete then the it will only compare
     /// the changing values to the already stored values. If the change is an
     /// insert then it will also check if the values mach the last performed query.
+    /// Communicators whose last query was [`QueryType::All`] match every
+    /// insert, so those are sent the whole insert without checking each value.
     pub fn get_interested_comm(
         &self,
         update: &DataChange<Key, Value>,
@@ -56,6 +96,9 @@ where
             .iter()
             .filter_map(|(comm, info)| {
                 let comm_update = match update {
+                    DataChange::Insert(_) if matches!(info.last_query, Some(QueryType::All)) => {
+                        update.clone()
+                    }
                     DataChange::Insert(values) => {
                         let new_values = values
                             .into_iter()
@@ -102,7 +145,10 @@ where
     ///
     /// Ignores the Update case since that doesnt add or remove new keys.
     pub fn update_info_from_change(&mut self, target: &Uuid, update: &DataChange<Key, Value>) {
-        let value_keys = &mut self.comm_to_info.get_mut(target).unwrap().value_keys;
+        let Some(info) = self.comm_to_info.get_mut(target) else {
+            return;
+        };
+        let value_keys = &mut info.value_keys;
         match update {
             DataChange::Insert(values) => {
                 value_keys.extend(values.keys().into_iter().cloned().collect_vec());
@@ -117,10 +163,22 @@ where
     /// Update the internal info object to reflect the data each communicator
     /// contains. Perfomed when the communicator queries for data.
     pub fn update_info_from_query(&mut self, target: &Uuid, fresh_data: &FreshData<Key, Value>) {
-        let value_keys = &mut self.comm_to_info.get_mut(target).unwrap().value_keys;
+        let Some(info) = self.comm_to_info.get_mut(target) else {
+            return;
+        };
+        let value_keys = &mut info.value_keys;
         value_keys.clear();
         value_keys.extend(fresh_data.keys().cloned());
     }
+
+    /// Same as [`update_info_from_query`][CommunicatorInfo::update_info_from_query]
+    /// but keeps the keys of the earlier chunks of a streamed query.
+    pub fn extend_info_from_chunk(&mut self, target: &Uuid, chunk: &FreshData<Key, Value>) {
+        let Some(info) = self.comm_to_info.get_mut(target) else {
+            return;
+        };
+        info.value_keys.extend(chunk.keys().cloned());
+    }
 }
 
 pub struct Info<Key, Value>
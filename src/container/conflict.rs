@@ -0,0 +1,91 @@
+//! Decides which value is kept when multiple inserts for the same key arrive
+//! in a single [`state_update`][super::DataContainer::state_update].
+
+use std::collections::HashMap;
+
+use crate::{change::ChangeType, KeyBounds, ValueBounds};
+
+use super::resolving_actions::Action;
+
+/// Combines two values of the same key into one, the first argument was
+/// recived earlier.
+pub type MergeFn<Value> = Box<dyn Fn(&Value, &Value) -> Value + Send + Sync>;
+
+/// Strategy used by the [`DataContainer`][super::DataContainer] to resolve
+/// conflicting inserts. Set it with
+/// [`set_conflict_strategy`][super::DataContainer::set_conflict_strategy].
+#[derive(Default)]
+pub enum ConflictStrategy<Value> {
+    /// Leaves the inserts untouched, the storage applies them in order so the
+    /// last one wins.
+    #[default]
+    LastWins,
+    /// Every conflicting insert writes the value that was recived first.
+    FirstWins,
+    /// Every conflicting insert writes the merged value. The function is
+    /// folded over the values in the order they were recived.
+    Merge(MergeFn<Value>),
+}
+
+impl<Value> ConflictStrategy<Value> {
+    fn pick(&self, first: &Value, second: &Value) -> Value
+    where
+        Value: Clone,
+    {
+        match self {
+            Self::LastWins => second.clone(),
+            Self::FirstWins => first.clone(),
+            Self::Merge(merge) => merge(first, second),
+        }
+    }
+
+    /// Rewrites the values of all inserts in `actions` that share a key with
+    /// another insert to the value chosen by the strategy.
+    pub(crate) fn resolve_inserts<Key>(&self, actions: &mut [Action<Key, Value>])
+    where
+        Key: KeyBounds,
+        Value: ValueBounds<Key>,
+    {
+        if matches!(self, Self::LastWins) {
+            return;
+        }
+
+        let mut winners: HashMap<Key, (usize, Value)> = HashMap::new();
+        for value in inserted_values(actions) {
+            winners
+                .entry(value.key().clone())
+                .and_modify(|(count, winner)| {
+                    *count += 1;
+                    *winner = self.pick(winner, value);
+                })
+                .or_insert_with(|| (1, value.clone()));
+        }
+
+        for value in inserted_values(actions) {
+            if let Some((count, winner)) = winners.get(value.key()) {
+                if *count > 1 {
+                    *value = winner.clone();
+                }
+            }
+        }
+    }
+}
+
+fn inserted_values<Key, Value>(
+    actions: &mut [Action<Key, Value>],
+) -> impl Iterator<Item = &mut Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    actions
+        .iter_mut()
+        .flat_map(|action| match action {
+            Action::Change(change) => match &mut change.action {
+                ChangeType::Insert(value) => vec![value],
+                ChangeType::InsertMany(values) => values.iter_mut().collect(),
+                _ => vec![],
+            },
+            Action::Query(_) => vec![],
+        })
+}
@@ -0,0 +1,53 @@
+//! Keeps a log of the errors the [`DataContainer`][super::DataContainer]
+//! observed while resolving actions.
+
+use std::collections::VecDeque;
+
+use crate::{change::ChangeError, query::QueryError};
+
+pub(crate) const DEFAULT_ERROR_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone)]
+pub enum ContainerError {
+    Change(ChangeError),
+    Query(QueryError),
+}
+
+/// Ring buffer of the most recent errors, once full the oldest error is
+/// dropped for every new one.
+pub(crate) struct ErrorLog {
+    errors: VecDeque<ContainerError>,
+    capacity: usize,
+}
+
+impl ErrorLog {
+    pub fn push(&mut self, error: ContainerError) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.errors.len() == self.capacity {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(error);
+    }
+
+    pub fn drain(&mut self) -> Vec<ContainerError> {
+        self.errors.drain(..).collect()
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.errors.len() > capacity {
+            self.errors.pop_front();
+        }
+    }
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self {
+            errors: VecDeque::with_capacity(DEFAULT_ERROR_CAPACITY),
+            capacity: DEFAULT_ERROR_CAPACITY,
+        }
+    }
+}
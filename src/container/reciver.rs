@@ -14,8 +14,12 @@ where
 {
     change_reciver: mpsc::Receiver<Change<Key, Value>>,
     query_reciver: mpsc::Receiver<DataQuery<Key, Value>>,
+    priority_query_reciver: mpsc::Receiver<DataQuery<Key, Value>>,
     bk_change_sender: mpsc::Sender<Change<Key, Value>>,
     bk_query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+    bk_priority_query_sender: mpsc::Sender<DataQuery<Key, Value>>,
+    /// Action recived by [`wait_for_action`][Reciver::wait_for_action].
+    woken_by: Option<Action<Key, Value>>,
 }
 
 impl<Key, Value> Reciver<Key, Value>
@@ -28,23 +32,68 @@ where
     ) -> (
         mpsc::Sender<Change<Key, Value>>,
         mpsc::Sender<DataQuery<Key, Value>>,
+        mpsc::Sender<DataQuery<Key, Value>>,
     ) {
-        (self.bk_change_sender.clone(), self.bk_query_sender.clone())
+        (
+            self.bk_change_sender.clone(),
+            self.bk_query_sender.clone(),
+            self.bk_priority_query_sender.clone(),
+        )
     }
 
-    pub fn recive_new(&mut self, cont_uuid: &Uuid) -> Vec<Action<Key, Value>> {
-        let mut new_actions: Vec<Action<Key, Value>> = vec![];
-        new_actions.extend(Self::loop_recive_all(cont_uuid, &mut self.change_reciver));
-        new_actions.extend(Self::loop_recive_all(cont_uuid, &mut self.query_reciver));
+    /// Recives at most `max_actions` new actions, changes are recived first
+    /// then high priority queries and finally all other queries.
+    ///
+    /// If queries are waiting, up to half of `max_actions` is reserved for
+    /// them so that they don't starve behind a large backlog of changes.
+    pub fn recive_new(&mut self, cont_uuid: &Uuid, max_actions: usize) -> Vec<Action<Key, Value>> {
+        let waiting_queries = self.priority_query_reciver.len() + self.query_reciver.len();
+        let reserved_for_queries = waiting_queries.min(max_actions / 2);
+
+        let mut new_actions: Vec<Action<Key, Value>> =
+            self.woken_by.take_if(|_| max_actions > 0).into_iter().collect();
+        new_actions.extend(Self::loop_recive_all(
+            cont_uuid,
+            &mut self.change_reciver,
+            (max_actions - reserved_for_queries).saturating_sub(new_actions.len()),
+        ));
+        new_actions.extend(Self::loop_recive_all(
+            cont_uuid,
+            &mut self.priority_query_reciver,
+            max_actions - new_actions.len(),
+        ));
+        new_actions.extend(Self::loop_recive_all(
+            cont_uuid,
+            &mut self.query_reciver,
+            max_actions - new_actions.len(),
+        ));
         new_actions
     }
 
+    /// Waits until any channel recived a new action. The action is kept and
+    /// returned first by the next [`recive_new`][Reciver::recive_new].
+    pub async fn wait_for_action(&mut self) {
+        if self.woken_by.is_some() {
+            return;
+        }
+        // NOTE: the recivers can't be closed since this holds on to a sender
+        // of each, so one of the branches always matches
+        let action = tokio::select! {
+            biased;
+            Some(change) = self.change_reciver.recv() => Action::from(change),
+            Some(query) = self.priority_query_reciver.recv() => Action::from(query),
+            Some(query) = self.query_reciver.recv() => Action::from(query),
+        };
+        self.woken_by = Some(action);
+    }
+
     fn loop_recive_all<T: Into<Action<Key, Value>>>(
         cont_uuid: &Uuid,
         reciver: &mut Receiver<T>,
+        max_actions: usize,
     ) -> Vec<Action<Key, Value>> {
         let mut actions = vec![];
-        loop {
+        while actions.len() < max_actions {
             match reciver.try_recv() {
                 Ok(val) => {
                     let action = val.into();
@@ -74,12 +123,16 @@ where
     fn default() -> Self {
         let (action_sender, action_reciver) = mpsc::channel(10);
         let (query_sender, query_reciver) = mpsc::channel(10);
+        let (priority_query_sender, priority_query_reciver) = mpsc::channel(10);
 
         Self {
             bk_change_sender: action_sender,
             change_reciver: action_reciver,
             bk_query_sender: query_sender,
             query_reciver,
+            bk_priority_query_sender: priority_query_sender,
+            priority_query_reciver,
+            woken_by: None,
         }
     }
 }
@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{fmt::Display, mem::Discriminant};
 
 use lazy_async_promise::{DirectCacheAccess, ImmediateValuePromise};
 use tokio::sync::oneshot;
@@ -7,11 +7,49 @@ use uuid::Uuid;
 
 use crate::{
     change::{Change, ChangeResponse, ChangeResult, DataChange},
-    query::{DataQuery, FreshData, QueryResponse, QueryResult},
+    query::{DataQuery, FreshData, QueryError, QueryResponse, QueryResult, QueryType},
     utils::PromiseUtilities,
     KeyBounds, ValueBounds,
 };
 
+use super::{
+    errors::ContainerError,
+    storage::{cascaded_delete_promise, Storage},
+};
+
+/// Identifies a query, so that the container can tell if a newer query of the
+/// same kind was sent by the same communicator in the meantime.
+pub struct QueryTicket<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    pub kind: Discriminant<QueryType<Key, Value>>,
+    pub sequence: u64,
+    /// See [`DataQuery::query_id`].
+    pub query_id: Option<u64>,
+    /// Wether the result replaces the values the communicator holds, e.g.
+    /// when the container repeats a [`QueryType::Latest`].
+    pub replaces: bool,
+}
+
+impl<Key, Value> Clone for QueryTicket<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Key, Value> Copy for QueryTicket<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+}
+
 pub enum ResolvingAction<Key, Value>
 where
     Key: KeyBounds,
@@ -24,7 +62,9 @@ where
     Query(
         ImmediateValuePromise<QueryResponse<Key, Value>>,
         Uuid,
-        oneshot::Sender<QueryResult>,
+        Option<oneshot::Sender<QueryResult>>,
+        Option<oneshot::Sender<FreshData<Key, Value>>>,
+        QueryTicket<Key, Value>,
     ),
 }
 
@@ -36,50 +76,125 @@ where
     pub fn poll_and_finished(&mut self) -> bool {
         match self {
             Self::Change(promise, _) => promise.poll_and_check_finished(),
-            Self::Query(promise, _, _) => promise.poll_and_check_finished(),
+            Self::Query(promise, _, _, _, _) => promise.poll_and_check_finished(),
         }
     }
 
-    pub fn resolve(self, cont_uuid: &Uuid) -> Option<ResolvedAction<Key, Value>> {
+    pub fn resolve<Writer: Storage<Key, Value>>(
+        self,
+        cont_uuid: &Uuid,
+        storage: &mut Writer,
+        max_query_result_size: Option<usize>,
+    ) -> Option<ResolvedAction<Key, Value>> {
         match self {
             ResolvingAction::Change(mut promise, sender) => {
                 promise.take_value().map(|change_response| {
+                    if let ChangeResponse::Cascade { action, cascaded } = change_response {
+                        let promise = cascaded_delete_promise(storage, action, cascaded);
+                        return Some(ResolvedAction::Pending(ResolvingAction::Change(
+                            promise, sender,
+                        )));
+                    }
+                    let rekeyed = match &change_response {
+                        ChangeResponse::Rekeyed { old, value } => {
+                            Some((old.clone(), value.clone()))
+                        }
+                        _ => None,
+                    };
                     let (data_change, change_result) = change_response.into();
+                    storage.after_change(&change_result);
+                    let error = match &change_result {
+                        ChangeResult::Error(err) => Some(ContainerError::Change(err.clone())),
+                        ChangeResult::Success | ChangeResult::Affected(_) => None,
+                    };
                     let _ = sender.send(change_result).map_err(|value| {
                         warn!(msg = format!("Change result could not be sent because reciver was dropped. Result was: [{value:?}]"), cont = cont_uuid.to_string())
                     });
                     debug!(msg = format!("Sent reponse of change result to communicator"), cont = cont_uuid.to_string());
-                    data_change.map(|data| ResolvedAction::Change(data))
+                    if let Some((old, value)) = rekeyed {
+                        return Some(ResolvedAction::Rekey { old, value });
+                    }
+                    data_change
+                        .map(|data| ResolvedAction::Change(data))
+                        .or(error.map(ResolvedAction::Error))
                 })?
             }
-            ResolvingAction::Query(mut promise, uuid, sender) => {
+            ResolvingAction::Query(mut promise, uuid, sender, fresh_data_sender, ticket) => {
                 promise.take_value().map(|query_response| {
+                    let query_response = match (query_response, max_query_result_size) {
+                        (QueryResponse::Ok(data), Some(max)) if data.len() > max => {
+                            warn!(msg = format!("Query returned {} values, which is more than the maximum of {max}", data.len()), cont = cont_uuid.to_string());
+                            QueryResponse::Err(QueryError::ResultTooLarge { size: data.len(), max })
+                        }
+                        (query_response, _) => query_response,
+                    };
                     let (fresh_data, result) = query_response.into();
-                    let _ = sender.send(result).map_err(|value| {
-                        warn!(msg = format!("Qeuery result could not be sent because reciver was dropped. Result was: [{value:?}]"), cont = cont_uuid.to_string())
-                    });
+                    let error = match &result {
+                        QueryResult::Error(err) => Some(ContainerError::Query(err.clone())),
+                        _ => None,
+                    };
+                    if let (Some(data), Some(fresh_data_sender)) = (&fresh_data, fresh_data_sender) {
+                        let _ = fresh_data_sender.send(data.clone());
+                    }
+                    if let Some(sender) = sender {
+                        let _ = sender.send(result).map_err(|value| {
+                            warn!(msg = format!("Qeuery result could not be sent because reciver was dropped. Result was: [{value:?}]"), cont = cont_uuid.to_string())
+                        });
+                    }
                     debug!(msg = format!("Sent response of query result to communicator [{uuid}]"), cont = cont_uuid.to_string());
-                    fresh_data.map(|data| ResolvedAction::Query(data, uuid))
+                    fresh_data
+                        .map(|data| ResolvedAction::Query(data, uuid, ticket))
+                        .or(error.map(ResolvedAction::Error))
                 })?
             }
         }
     }
 
+    /// Wether the result of this action might be sent to the communicator.
+    /// Any change might interest every communicator.
+    pub fn might_reach(&self, communicator_uuid: &Uuid) -> bool {
+        match self {
+            Self::Change(_, _) => true,
+            Self::Query(_, origin, _, _, _) => origin == communicator_uuid,
+        }
+    }
+
     pub fn action_type(&self) -> &str {
         match self {
             Self::Change(_, _) => "change",
-            Self::Query(_, _, _) => "query",
+            Self::Query(_, _, _, _, _) => "query",
         }
     }
 }
 
+/// Query whose result the storage returns in chunks, see
+/// [`Storage::query_streamed`].
+pub struct RunningStream<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    pub reciver: tokio::sync::mpsc::Receiver<QueryResponse<Key, Value>>,
+    pub origin_uuid: Uuid,
+    pub query_id: Option<u64>,
+    pub response_sender: oneshot::Sender<QueryResult>,
+    /// Number of values sent to the communicator so far.
+    pub fetched: usize,
+}
+
 pub enum ResolvedAction<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
     Change(DataChange<Key, Value>),
-    Query(FreshData<Key, Value>, Uuid),
+    /// The value was moved from the key `old` by a `ChangeType::Rekey`.
+    Rekey { old: Key, value: Value },
+    Query(FreshData<Key, Value>, Uuid, QueryTicket<Key, Value>),
+    Error(ContainerError),
+    /// The action needs another round trip to the storage, e.g. the delete
+    /// that follows the [`Storage::on_delete_cascade`] hooks.
+    Pending(ResolvingAction<Key, Value>),
 }
 
 pub enum Action<Key, Value>
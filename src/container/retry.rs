@@ -0,0 +1,26 @@
+//! How [`init_with_retry`][super::DataContainer::init_with_retry] retries a
+//! storage that fails to initialize, e.g. because the database isn't up yet.
+
+use std::time::Duration;
+
+/// Every failed attempt is followed by a pause that starts at
+/// `initial_backoff` and doubles up to `max_backoff`. Retrying stops after
+/// `max_attempts` attempts or once `timeout` has passed since the first one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
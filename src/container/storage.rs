@@ -1,11 +1,26 @@
 //! Any implementor of the [`Storage`] trait can act as the "database" for the 
 //! system
 
-use futures::future::BoxFuture;
+pub mod caching;
+pub mod in_memory;
+pub mod sharded;
+#[cfg(feature = "sqlx")]
+pub mod sql;
+
+use std::{collections::HashSet, error::Error, fmt::Display, iter, sync::Arc};
+
+use futures::future::{join_all, BoxFuture};
+use itertools::Itertools;
 use lazy_async_promise::ImmediateValuePromise;
+use tokio::sync::mpsc;
 use tracing::debug;
 
-use crate::{change::{ChangeResponse, ChangeResult, ChangeType}, query::{Predicate, QueryResponse, QueryType}};
+use crate::{
+    change::{ChangeError, ChangeResponse, ChangeResult, ChangeType, DataChange, Modifier},
+    query::{
+        FreshData, GroupFn, Predicate, QueryError, QueryResponse, QueryType, Since, SortKey,
+    },
+};
 
 use super::{
     KeyBounds, ValueBounds,
@@ -21,6 +36,23 @@ where
 {
     type InitArgs;
     fn init(args: Self::InitArgs) -> impl InitFuture<Self>;
+    /// Same as [`init`][Storage::init] but for storages that can fail to
+    /// initialize, e.g. when the database can't be reached. By default this
+    /// never fails.
+    fn try_init(args: Self::InitArgs) -> impl InitFuture<Result<Self, InitError>>
+    where
+        Self: Sized,
+    {
+        let init_future = Self::init(args);
+        async move { Ok(init_future.await) }
+    }
+
+    /// Called once by the container right after the storage was initialized
+    /// and before any change or query reaches it, e.g. to run schema
+    /// migrations. Does nothing by default.
+    fn migrate(&mut self) -> impl Future<Result<(), ChangeError>> {
+        async move { Ok(()) }
+    }
 
     fn insert(&mut self, value: &Value) -> impl Future<ChangeResult>;
     fn insert_many(&mut self, values: &[Value]) -> impl Future<ChangeResult>;
@@ -28,21 +60,173 @@ where
     fn update_many(&mut self, values: &[Value]) -> impl Future<ChangeResult>;
     fn delete(&mut self, key: &Key) -> impl Future<ChangeResult>;
     fn delete_many(&mut self, keys: &[Key]) -> impl Future<ChangeResult>;
+    /// Resolves a [`ChangeType::Move`] by giving the value with `key` a new
+    /// position after `after` and before `before`, returning the updated value.
+    ///
+    /// Not every storage has a notion of order, so by default this is unsupported.
+    fn reorder(
+        &mut self,
+        _key: &Key,
+        _before: Option<&Key>,
+        _after: Option<&Key>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        async move { Err(ChangeError::Unsupported(String::from("reorder"))) }
+    }
+    /// Resolves a [`ChangeType::Modify`] by applying `f` to the current value
+    /// with `key` and writing it back, returning the updated value. Nothing
+    /// else may change the value in between.
+    ///
+    /// By default this is unsupported.
+    fn modify(
+        &mut self,
+        _key: &Key,
+        _f: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        async move { Err(ChangeError::Unsupported(String::from("modify"))) }
+    }
+
+    /// Resolves a [`ChangeType::InsertIfAbsentMany`] by inserting only the
+    /// values whose key doesn't exist yet, returning the inserted values.
+    ///
+    /// By default this is unsupported.
+    fn insert_if_absent_many(
+        &mut self,
+        _values: &[Value],
+    ) -> impl Future<Result<Vec<Value>, ChangeError>> {
+        async move { Err(ChangeError::Unsupported(String::from("insert_if_absent_many"))) }
+    }
+
+    /// Resolves a [`ChangeType::Rekey`] by removing the value with `old`,
+    /// applying `set_key` to it and storing it under `new`, returning the
+    /// moved value. Fails if there is no value with `old` or one with `new`.
+    ///
+    /// By default this is unsupported.
+    fn rekey(
+        &mut self,
+        _old: &Key,
+        _new: &Key,
+        _set_key: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        async move { Err(ChangeError::Unsupported(String::from("rekey"))) }
+    }
 
     fn get_all(&mut self) -> impl Future<QueryResponse<Key, Value>>;
     fn get_by_id(&mut self, key: Key) -> impl Future<QueryResponse<Key, Value>>;
     // TODO: this function could technically have a default implementation
     // where it just uses the predicate function to do a search
     fn get_by_ids(&mut self, keys: Vec<Key>) -> impl Future<QueryResponse<Key, Value>>;
+    /// By default this fetches all values with [`get_all`][Storage::get_all]
+    /// and filters them afterwards. Storages that can search by the predicate
+    /// themselves should override this.
     fn get_by_predicate(
         &mut self,
         predicate: Predicate<Value>,
-    ) -> impl Future<QueryResponse<Key, Value>>;
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        filter_response(self.get_all(), predicate)
+    }
+
+    /// By default this searches with the predicate of the cursor through
+    /// [`get_by_predicate`][Storage::get_by_predicate]. Storages that keep
+    /// track of versions can take the cursor with [`Since::cursor`] instead.
+    fn get_changed_since(&mut self, since: Since<Value>) -> impl Future<QueryResponse<Key, Value>> {
+        self.get_by_predicate(since.predicate())
+    }
+
+    /// By default this fetches the values of `base_keys` with
+    /// [`get_by_ids`][Storage::get_by_ids] and filters them afterwards.
+    fn get_intersecting(
+        &mut self,
+        base_keys: Vec<Key>,
+        predicate: Predicate<Value>,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        filter_response(self.get_by_ids(base_keys), predicate)
+    }
+
+    /// Resolves a [`QueryType::Latest`], the returned data should have the
+    /// number of all values as its [`total`][FreshData::with_total].
+    ///
+    /// By default this fetches every value with [`get_all`][Storage::get_all]
+    /// and only keeps the greatest ones. Storages that can sort and limit
+    /// themselves, e.g. with an `ORDER BY` and `LIMIT`, should override this.
+    fn get_latest(
+        &mut self,
+        by: SortKey<Value>,
+        limit: usize,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        let all_future = self.get_all();
+        async move {
+            match all_future.await {
+                QueryResponse::Ok(fresh_data) => {
+                    let total = fresh_data.len();
+                    let mut values = fresh_data.values().cloned().collect_vec();
+                    if limit < values.len() {
+                        values.select_nth_unstable_by(limit, |first, second| {
+                            by.compare(second, first)
+                        });
+                        values.truncate(limit);
+                    }
+                    QueryResponse::Ok(FreshData::from(values).with_total(total))
+                }
+                other => other,
+            }
+        }
+    }
+
+    /// By default this fetches every value with [`get_all`][Storage::get_all]
+    /// and counts them afterwards. Storages that can count without loading
+    /// the values, e.g. with a `GROUP BY`, should override this.
+    fn group_count(&mut self, group_by: GroupFn<Value>) -> impl Future<QueryResponse<Key, Value>> {
+        let all_future = self.get_all();
+        async move {
+            match all_future.await {
+                QueryResponse::Ok(fresh_data) => QueryResponse::Groups(
+                    fresh_data.values().map(|value| group_by(value)).counts(),
+                ),
+                other => other,
+            }
+        }
+    }
+
+    /// Keys of the values that are deleted together with the value with
+    /// `key`, e.g. its children and their children. The container asks for
+    /// them before the delete is handed to the storage and then deletes them
+    /// together with `key`, so the storage doesn't have to cascade itself.
+    /// The keys are included in the delete sent to the communicators.
+    ///
+    /// By default nothing cascades.
+    fn on_delete_cascade(&mut self, _key: &Key) -> impl Future<Vec<Key>> {
+        async move { vec![] }
+    }
+
+    /// Advanced features this storage supports. By default none are.
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities::default()
+    }
+
+    /// Called right before a change is handed to the storage.
+    fn before_change(&mut self, _change: &ChangeType<Key, Value>) {}
+    /// Called by the container once the result of a change is known.
+    fn after_change(&mut self, _result: &ChangeResult) {}
+    /// Changes made to the storage by someone other than the container, e.g.
+    /// through database notifications. The container relays them to every
+    /// interested communicator. Called once when the container is initialized,
+    /// by default there is no stream.
+    fn external_change_stream(&mut self) -> Option<mpsc::Receiver<DataChange<Key, Value>>> {
+        None
+    }
+    /// Called when the stream of external changes ended, e.g. because the
+    /// notification connection dropped, and on every following state update
+    /// until a new stream is returned. Since changes might have been missed in
+    /// between, every communicator then repeats its last query.
+    fn resubscribe(&mut self) -> Option<mpsc::Receiver<DataChange<Key, Value>>> {
+        self.external_change_stream()
+    }
 
     fn handle_change(
         &mut self,
         action: ChangeType<Key, Value>,
     ) -> ImmediateValuePromise<ChangeResponse<Key, Value>> {
+        self.before_change(&action);
         // TODO: This should maybe be caught on a higher level and not this far
         // down the cain. Maybe on the communicator level. Although here its easier
         // since its a clear point where any interaction passes through
@@ -58,8 +242,31 @@ where
             ChangeType::InsertMany(values) => to_boxed(self.insert_many(values)),
             ChangeType::Update(value) => to_boxed(self.update(value)),
             ChangeType::UpdateMany(values) => to_boxed(self.update_many(values)),
-            ChangeType::Delete(key) => to_boxed(self.delete(key)),
-            ChangeType::DeleteMany(values) => to_boxed(self.delete_many(values)),
+            ChangeType::Delete(key) => {
+                let cascade_future = to_boxed(self.on_delete_cascade(key));
+                return cascade_promise(action, vec![cascade_future]);
+            }
+            ChangeType::DeleteMany(keys) => {
+                let cascade_futures = keys
+                    .iter()
+                    .map(|key| to_boxed(self.on_delete_cascade(key)))
+                    .collect_vec();
+                return cascade_promise(action, cascade_futures);
+            }
+            ChangeType::Move { key, before, after } => {
+                let reorder_future = to_boxed(self.reorder(key, before.as_ref(), after.as_ref()));
+                return updated_value_promise(reorder_future);
+            }
+            ChangeType::Modify { key, f } => {
+                return updated_value_promise(to_boxed(self.modify(key, f.clone())));
+            }
+            ChangeType::InsertIfAbsentMany(values) => {
+                return inserted_values_promise(to_boxed(self.insert_if_absent_many(values)));
+            }
+            ChangeType::Rekey { old, new, set_key } => {
+                let rekey_future = to_boxed(self.rekey(old, new, set_key.clone()));
+                return rekeyed_value_promise(old.clone(), rekey_future);
+            }
         };
         ImmediateValuePromise::new(async move {
             Ok(ChangeResponse::from_type_and_result(
@@ -72,14 +279,85 @@ where
         &mut self,
         query: QueryType<Key, Value>,
     ) -> ImmediateValuePromise<QueryResponse<Key, Value>> {
-        let query_future = match query {
-            QueryType::All => to_boxed(self.get_all()),
-            QueryType::GetById(id) => to_boxed(self.get_by_id(id)),
-            QueryType::GetByIds(ids) => to_boxed(self.get_by_ids(ids)),
-            QueryType::Predicate(pred) => to_boxed(self.get_by_predicate(pred)),
-        };
+        let query_future = query_future(self, query);
         ImmediateValuePromise::new(async move { Ok(query_future.await) })
     }
+    /// Returns the result of the query in chunks, e.g. for large results
+    /// that should be shown while they are still loading. The query is done
+    /// once the sender is dropped, an error ends it early.
+    ///
+    /// By default the whole result is sent as a single chunk.
+    fn query_streamed(
+        &mut self,
+        query: QueryType<Key, Value>,
+    ) -> mpsc::Receiver<QueryResponse<Key, Value>> {
+        let query_future = query_future(self, query);
+        let (sender, reciver) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let _ = sender.send(query_future.await).await;
+        });
+        reciver
+    }
+}
+
+/// Routes the query to the matching method of the storage.
+fn query_future<Key, Value, Writer>(
+    storage: &mut Writer,
+    query: QueryType<Key, Value>,
+) -> BoxFuture<'static, QueryResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Writer: Storage<Key, Value> + ?Sized,
+{
+    match query {
+        QueryType::All => to_boxed(storage.get_all()),
+        QueryType::GetById(id) => to_boxed(storage.get_by_id(id)),
+        QueryType::GetByIds(ids) => to_boxed(storage.get_by_ids(ids)),
+        QueryType::Predicate(pred) => to_boxed(storage.get_by_predicate(pred)),
+        QueryType::ChangedSince(since) => to_boxed(storage.get_changed_since(since)),
+        QueryType::Intersect { base_keys, pred } => {
+            to_boxed(storage.get_intersecting(base_keys, pred))
+        }
+        QueryType::GroupCount { group_by } => to_boxed(storage.group_count(group_by)),
+        QueryType::Latest { by, limit } => to_boxed(storage.get_latest(by, limit)),
+        QueryType::Not(query) if !query.matches_per_value() => {
+            let unsupported = QueryError::Unsupported(format!("Not({query})"));
+            to_boxed(async move { QueryResponse::Err(unsupported) })
+        }
+        QueryType::Not(query) => {
+            let query = *query;
+            to_boxed(storage.get_by_predicate(Arc::new(move |value: &Value| {
+                !query.apply(value)
+            })))
+        }
+    }
+}
+
+/// Returned by [`Storage::try_init`] if the storage couldn't be initialized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitError(pub String);
+
+impl Display for InitError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{self:?}")
+    }
+}
+
+impl Error for InitError {}
+
+/// Advanced features a [`Storage`] may support, so that generic code doesn't
+/// have to assume the worst. See [`Storage::capabilities`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageCapabilities {
+    /// Changes with multiple values are applied atomically.
+    pub transactions: bool,
+    /// Values can be queried by a range of keys.
+    pub range: bool,
+    /// Query results can be streamed instead of returned all at once.
+    pub streaming: bool,
+    /// The storage can notice changes made by someone other than the container.
+    pub external_changes: bool,
 }
 
 pub trait InitFuture<FutOutput>
@@ -108,6 +386,142 @@ where
 {
 }
 
+/// Only keeps the values of a successful query response that match the predicate.
+fn filter_response<Key, Value>(
+    response_future: impl Future<QueryResponse<Key, Value>>,
+    predicate: Predicate<Value>,
+) -> impl Future<QueryResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    async move {
+        match response_future.await {
+            QueryResponse::Ok(mut fresh_data) => {
+                fresh_data.retain(|_, value| predicate(value));
+                QueryResponse::Ok(fresh_data)
+            }
+            err => err,
+        }
+    }
+}
+
+/// Turns the value returned by a storage for a [`ChangeType::Move`] or
+/// [`ChangeType::Modify`] into an update of that value.
+fn updated_value_promise<Key, Value>(
+    value_future: BoxFuture<'static, Result<Value, ChangeError>>,
+) -> ImmediateValuePromise<ChangeResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    ImmediateValuePromise::new(async move {
+        Ok(match value_future.await {
+            Ok(value) => ChangeResponse::Ok(DataChange::Update(vec![value])),
+            Err(err) => ChangeResponse::Err(err),
+        })
+    })
+}
+
+/// Turns the values returned by a storage for a
+/// [`ChangeType::InsertIfAbsentMany`] into an insert of only those values.
+fn inserted_values_promise<Key, Value>(
+    values_future: BoxFuture<'static, Result<Vec<Value>, ChangeError>>,
+) -> ImmediateValuePromise<ChangeResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    ImmediateValuePromise::new(async move {
+        Ok(match values_future.await {
+            Ok(values) => {
+                let inserted = values.len();
+                ChangeResponse::Affected(DataChange::Insert(values), inserted)
+            }
+            Err(err) => ChangeResponse::Err(err),
+        })
+    })
+}
+
+/// Collects the keys that cascade from the deleted keys, see
+/// [`Storage::on_delete_cascade`]. The container then hands the delete
+/// together with them to [`cascaded_delete_promise`].
+fn cascade_promise<Key, Value>(
+    action: ChangeType<Key, Value>,
+    cascade_futures: Vec<BoxFuture<'static, Vec<Key>>>,
+) -> ImmediateValuePromise<ChangeResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    ImmediateValuePromise::new(async move {
+        let mut seen = match &action {
+            ChangeType::Delete(key) => HashSet::from([key.clone()]),
+            ChangeType::DeleteMany(keys) => keys.iter().cloned().collect(),
+            _ => HashSet::new(),
+        };
+        let cascaded = join_all(cascade_futures)
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|key| seen.insert(key.clone()))
+            .collect_vec();
+        Ok(ChangeResponse::Cascade { action, cascaded })
+    })
+}
+
+/// Deletes the keys of the `action` together with the keys that `cascaded`
+/// from them.
+pub(crate) fn cascaded_delete_promise<Key, Value, Writer>(
+    storage: &mut Writer,
+    action: ChangeType<Key, Value>,
+    cascaded: Vec<Key>,
+) -> ImmediateValuePromise<ChangeResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Writer: Storage<Key, Value> + ?Sized,
+{
+    let action = match action {
+        ChangeType::Delete(key) if !cascaded.is_empty() => {
+            ChangeType::DeleteMany(iter::once(key).chain(cascaded).collect())
+        }
+        ChangeType::DeleteMany(keys) => {
+            ChangeType::DeleteMany(keys.into_iter().chain(cascaded).collect())
+        }
+        action => action,
+    };
+    let delete_future = match &action {
+        ChangeType::Delete(key) => to_boxed(storage.delete(key)),
+        ChangeType::DeleteMany(keys) => to_boxed(storage.delete_many(keys)),
+        _ => return storage.handle_change(action),
+    };
+    ImmediateValuePromise::new(async move {
+        Ok(ChangeResponse::from_type_and_result(
+            action,
+            delete_future.await,
+        ))
+    })
+}
+
+/// Turns the value returned by a storage for a [`ChangeType::Rekey`] into the
+/// response that tells the container which key the value was moved from.
+fn rekeyed_value_promise<Key, Value>(
+    old: Key,
+    value_future: BoxFuture<'static, Result<Value, ChangeError>>,
+) -> ImmediateValuePromise<ChangeResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    ImmediateValuePromise::new(async move {
+        Ok(match value_future.await {
+            Ok(value) => ChangeResponse::Rekeyed { old, value },
+            Err(err) => ChangeResponse::Err(err),
+        })
+    })
+}
+
 fn to_boxed<FutOutput>(fut: impl Future<FutOutput>) -> BoxFuture<'static, FutOutput>
 where
     FutOutput: Clone + Send + 'static,
@@ -0,0 +1,308 @@
+//! [`Storage`] implementation that puts a fast storage as a cache in front of
+//! a slow one, e.g. an [`InMemoryStorage`][super::in_memory::InMemoryStorage]
+//! in front of a database.
+
+use std::sync::{Arc, Mutex};
+
+use futures::future::{join, BoxFuture};
+use itertools::Itertools;
+
+use crate::{
+    change::{ChangeError, ChangeResult, Modifier},
+    query::{Predicate, QueryResponse, Since},
+    KeyBounds, ValueBounds,
+};
+
+use super::{to_boxed, Future, InitFuture, Storage, StorageCapabilities};
+
+/// Serves reads by key from the cache and only asks the backend on a miss,
+/// every value read from the backend is put into the cache. Reads that aren't
+/// restricted to keys, like [`get_all`][Storage::get_all], always go to the
+/// backend since the cache might not hold every value.
+///
+/// Changes are written through to both. If the backend rejects a change the
+/// values of it are evicted from the cache again. Neither this nor filling
+/// the cache after a miss is atomic, so a change racing a read miss may leave
+/// an outdated value in the cache.
+pub struct CachingStorage<Key, Value, Cache, Backend>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Cache: Storage<Key, Value>,
+    Backend: Storage<Key, Value>,
+{
+    cache: Arc<Mutex<Cache>>,
+    backend: Arc<Mutex<Backend>>,
+    _marker: std::marker::PhantomData<fn() -> (Key, Value)>,
+}
+
+impl<Key, Value, Cache, Backend> CachingStorage<Key, Value, Cache, Backend>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Cache: Storage<Key, Value> + 'static,
+    Backend: Storage<Key, Value> + 'static,
+{
+    /// Gives `f` direct access to the cache, e.g. to clear it.
+    pub fn with_cache<T>(&self, f: impl FnOnce(&mut Cache) -> T) -> T {
+        f(&mut self.cache.lock().unwrap())
+    }
+
+    /// Gives `f` direct access to the backend, bypassing the cache.
+    pub fn with_backend<T>(&self, f: impl FnOnce(&mut Backend) -> T) -> T {
+        f(&mut self.backend.lock().unwrap())
+    }
+
+    /// Writes the change to both storages, the result of the backend is
+    /// returned. If the backend failed the keys are evicted from the cache.
+    fn write_through(
+        &mut self,
+        keys: Vec<Key>,
+        backend_future: impl Future<ChangeResult>,
+        cache_future: impl Future<ChangeResult>,
+    ) -> impl Future<ChangeResult> {
+        let cache = self.cache.clone();
+        async move {
+            let (backend_result, _) = join(backend_future, cache_future).await;
+            if let ChangeResult::Error(_) = backend_result {
+                let evict_future = to_boxed(cache.lock().unwrap().delete_many(&keys));
+                evict_future.await;
+            }
+            backend_result
+        }
+    }
+
+    /// Sends the query to the backend and puts the values it returned into
+    /// the cache.
+    fn read_through(
+        &mut self,
+        read: impl FnOnce(&mut Backend) -> QueryFuture<Key, Value>,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        let backend_future = read(&mut self.backend.lock().unwrap());
+        let cache = self.cache.clone();
+        async move { fill_cache(&cache, backend_future.await).await }
+    }
+
+    /// Serves the query from the cache if it returned every key, otherwise
+    /// from the backend like [`read_through`][Self::read_through].
+    fn read_cached(
+        &mut self,
+        keys: Vec<Key>,
+        read_cache: impl FnOnce(&mut Cache) -> QueryFuture<Key, Value>,
+        read_backend: impl FnOnce(&mut Backend) -> QueryFuture<Key, Value> + Send + 'static,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        let cache_future = read_cache(&mut self.cache.lock().unwrap());
+        let cache = self.cache.clone();
+        let backend = self.backend.clone();
+        async move {
+            if let QueryResponse::Ok(fresh_data) = cache_future.await {
+                if keys.iter().all(|key| fresh_data.contains_key(key)) {
+                    return QueryResponse::Ok(fresh_data);
+                }
+            }
+            let backend_future = read_backend(&mut backend.lock().unwrap());
+            fill_cache(&cache, backend_future.await).await
+        }
+    }
+
+    /// Writes the value the backend resolved a change into to the cache.
+    fn cache_resolved(
+        &mut self,
+        resolved_future: impl Future<Result<Value, ChangeError>>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let cache = self.cache.clone();
+        async move {
+            let value = resolved_future.await?;
+            let update_future = to_boxed(cache.lock().unwrap().insert(&value));
+            update_future.await;
+            Ok(value)
+        }
+    }
+}
+
+type QueryFuture<Key, Value> = BoxFuture<'static, QueryResponse<Key, Value>>;
+
+fn keys_of<Key, Value>(values: &[Value]) -> Vec<Key>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    values.iter().map(|value| value.key().clone()).collect_vec()
+}
+
+/// Puts the values of a successful query response into the cache.
+async fn fill_cache<Key, Value, Cache>(
+    cache: &Mutex<Cache>,
+    response: QueryResponse<Key, Value>,
+) -> QueryResponse<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Cache: Storage<Key, Value>,
+{
+    if let QueryResponse::Ok(fresh_data) = &response {
+        let values = fresh_data.values().cloned().collect_vec();
+        let insert_future = to_boxed(cache.lock().unwrap().insert_many(&values));
+        insert_future.await;
+    }
+    response
+}
+
+impl<Key, Value, Cache, Backend> Storage<Key, Value> for CachingStorage<Key, Value, Cache, Backend>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Cache: Storage<Key, Value> + 'static,
+    Backend: Storage<Key, Value> + 'static,
+    Cache::InitArgs: Send + 'static,
+    Backend::InitArgs: Send + 'static,
+{
+    /// The init arguments of the cache and of the backend.
+    type InitArgs = (Cache::InitArgs, Backend::InitArgs);
+
+    fn init((cache_args, backend_args): Self::InitArgs) -> impl InitFuture<Self> {
+        async move {
+            Self {
+                cache: Arc::new(Mutex::new(Cache::init(cache_args).await)),
+                backend: Arc::new(Mutex::new(Backend::init(backend_args).await)),
+                _marker: std::marker::PhantomData,
+            }
+        }
+    }
+
+    fn migrate(&mut self) -> impl Future<Result<(), ChangeError>> {
+        to_boxed(self.backend.lock().unwrap().migrate())
+    }
+
+    fn insert(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().insert(value));
+        let cache_future = to_boxed(self.cache.lock().unwrap().insert(value));
+        self.write_through(vec![value.key().clone()], backend_future, cache_future)
+    }
+
+    fn insert_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().insert_many(values));
+        let cache_future = to_boxed(self.cache.lock().unwrap().insert_many(values));
+        self.write_through(keys_of(values), backend_future, cache_future)
+    }
+
+    fn update(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().update(value));
+        let cache_future = to_boxed(self.cache.lock().unwrap().update(value));
+        self.write_through(vec![value.key().clone()], backend_future, cache_future)
+    }
+
+    fn update_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().update_many(values));
+        let cache_future = to_boxed(self.cache.lock().unwrap().update_many(values));
+        self.write_through(keys_of(values), backend_future, cache_future)
+    }
+
+    fn delete(&mut self, key: &Key) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().delete(key));
+        let cache_future = to_boxed(self.cache.lock().unwrap().delete(key));
+        self.write_through(vec![key.clone()], backend_future, cache_future)
+    }
+
+    fn delete_many(&mut self, keys: &[Key]) -> impl Future<ChangeResult> {
+        let backend_future = to_boxed(self.backend.lock().unwrap().delete_many(keys));
+        let cache_future = to_boxed(self.cache.lock().unwrap().delete_many(keys));
+        self.write_through(keys.to_vec(), backend_future, cache_future)
+    }
+
+    fn reorder(
+        &mut self,
+        key: &Key,
+        before: Option<&Key>,
+        after: Option<&Key>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let reorder_future = to_boxed(self.backend.lock().unwrap().reorder(key, before, after));
+        self.cache_resolved(reorder_future)
+    }
+
+    fn modify(&mut self, key: &Key, f: Modifier<Value>) -> impl Future<Result<Value, ChangeError>> {
+        let modify_future = to_boxed(self.backend.lock().unwrap().modify(key, f));
+        self.cache_resolved(modify_future)
+    }
+
+    fn rekey(
+        &mut self,
+        old: &Key,
+        new: &Key,
+        set_key: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let rekey_future = to_boxed(self.backend.lock().unwrap().rekey(old, new, set_key));
+        let evict_future = to_boxed(self.cache.lock().unwrap().delete(old));
+        let moved_future = self.cache_resolved(rekey_future);
+        async move {
+            let value = moved_future.await?;
+            evict_future.await;
+            Ok(value)
+        }
+    }
+
+    fn insert_if_absent_many(
+        &mut self,
+        values: &[Value],
+    ) -> impl Future<Result<Vec<Value>, ChangeError>> {
+        let insert_future = to_boxed(self.backend.lock().unwrap().insert_if_absent_many(values));
+        let cache = self.cache.clone();
+        async move {
+            let inserted = insert_future.await?;
+            let cache_future = to_boxed(cache.lock().unwrap().insert_many(&inserted));
+            cache_future.await;
+            Ok(inserted)
+        }
+    }
+
+    /// Asks the backend, the cascading keys are then evicted from the cache
+    /// by the following delete.
+    fn on_delete_cascade(&mut self, key: &Key) -> impl Future<Vec<Key>> {
+        to_boxed(self.backend.lock().unwrap().on_delete_cascade(key))
+    }
+
+    fn get_all(&mut self) -> impl Future<QueryResponse<Key, Value>> {
+        self.read_through(|backend| to_boxed(backend.get_all()))
+    }
+
+    fn get_by_id(&mut self, key: Key) -> impl Future<QueryResponse<Key, Value>> {
+        let cache_key = key.clone();
+        self.read_cached(
+            vec![key.clone()],
+            |cache| to_boxed(cache.get_by_id(cache_key)),
+            |backend| to_boxed(backend.get_by_id(key)),
+        )
+    }
+
+    fn get_by_ids(&mut self, keys: Vec<Key>) -> impl Future<QueryResponse<Key, Value>> {
+        let (cache_keys, backend_keys) = (keys.clone(), keys.clone());
+        self.read_cached(
+            keys,
+            |cache| to_boxed(cache.get_by_ids(cache_keys)),
+            |backend| to_boxed(backend.get_by_ids(backend_keys)),
+        )
+    }
+
+    fn get_by_predicate(
+        &mut self,
+        predicate: Predicate<Value>,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        self.read_through(|backend| to_boxed(backend.get_by_predicate(predicate)))
+    }
+
+    fn get_changed_since(&mut self, since: Since<Value>) -> impl Future<QueryResponse<Key, Value>> {
+        self.read_through(|backend| to_boxed(backend.get_changed_since(since)))
+    }
+
+    fn get_intersecting(
+        &mut self,
+        base_keys: Vec<Key>,
+        predicate: Predicate<Value>,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        self.read_through(|backend| to_boxed(backend.get_intersecting(base_keys, predicate)))
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        self.backend.lock().unwrap().capabilities()
+    }
+}
@@ -0,0 +1,181 @@
+//! [`Storage`] implementation that only keeps the values in memory, e.g. for
+//! prototyping an application before the real storage exists.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use crate::{
+    change::{ChangeError, ChangeResult, Modifier},
+    query::{QueryError, QueryResponse},
+    KeyBounds, ValueBounds,
+};
+
+use super::{Future, InitFuture, Storage};
+
+/// Keeps all values in a [`HashMap`], nothing is persisted.
+///
+/// Updating a value that isn't present fails with a
+/// [`ChangeError::DatabaseError`], deleting one succeeds and reports how many
+/// values existed with [`ChangeResult::Affected`]. Queries for keys that
+/// aren't present fail with [`QueryError::NotPresent`].
+pub struct InMemoryStorage<Key, Value> {
+    values: Arc<HashMap<Key, Value>>,
+}
+
+impl<Key, Value> Default for InMemoryStorage<Key, Value> {
+    fn default() -> Self {
+        Self {
+            values: Arc::new(HashMap::new()),
+        }
+    }
+}
+
+impl<Key, Value> InMemoryStorage<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    pub fn values(&self) -> &HashMap<Key, Value> {
+        &self.values
+    }
+
+    fn values_mut(&mut self) -> &mut HashMap<Key, Value> {
+        Arc::make_mut(&mut self.values)
+    }
+
+    fn update_values<'a>(&mut self, values: impl IntoIterator<Item = &'a Value>) -> ChangeResult {
+        for value in values {
+            let Some(old_value) = self.values_mut().get_mut(value.key()) else {
+                return ChangeResult::Error(ChangeError::DatabaseError(format!(
+                    "No value with key [{:?}]",
+                    value.key()
+                )));
+            };
+            *old_value = value.clone();
+        }
+        ChangeResult::Success
+    }
+}
+
+impl<Key, Value> Storage<Key, Value> for InMemoryStorage<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    type InitArgs = ();
+
+    fn init(_: Self::InitArgs) -> impl InitFuture<Self> {
+        async move { Self::default() }
+    }
+
+    fn insert(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        self.values_mut().insert(value.key().clone(), value.clone());
+        async move { ChangeResult::Success }
+    }
+
+    fn insert_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        self.values_mut()
+            .extend(values.iter().map(|value| (value.key().clone(), value.clone())));
+        async move { ChangeResult::Success }
+    }
+
+    fn update(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        let result = self.update_values([value]);
+        async move { result }
+    }
+
+    fn update_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        let result = self.update_values(values);
+        async move { result }
+    }
+
+    fn delete(&mut self, key: &Key) -> impl Future<ChangeResult> {
+        let affected = usize::from(self.values_mut().remove(key).is_some());
+        async move { ChangeResult::Affected(affected) }
+    }
+
+    fn delete_many(&mut self, keys: &[Key]) -> impl Future<ChangeResult> {
+        let values = self.values_mut();
+        let affected = keys
+            .iter()
+            .filter(|key| values.remove(key).is_some())
+            .count();
+        async move { ChangeResult::Affected(affected) }
+    }
+
+    fn modify(
+        &mut self,
+        key: &Key,
+        f: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let result = match self.values_mut().get_mut(key) {
+            Some(value) => {
+                f(value);
+                Ok(value.clone())
+            }
+            None => Err(ChangeError::DatabaseError(format!("No value with key [{key:?}]"))),
+        };
+        async move { result }
+    }
+
+    fn rekey(
+        &mut self,
+        old: &Key,
+        new: &Key,
+        set_key: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let values = self.values_mut();
+        let result = if values.contains_key(new) {
+            Err(ChangeError::DatabaseError(format!("Key [{new:?}] is already taken")))
+        } else {
+            match values.remove(old) {
+                Some(mut value) => {
+                    set_key(&mut value);
+                    values.insert(new.clone(), value.clone());
+                    Ok(value)
+                }
+                None => Err(ChangeError::DatabaseError(format!("No value with key [{old:?}]"))),
+            }
+        };
+        async move { result }
+    }
+
+    fn insert_if_absent_many(
+        &mut self,
+        values: &[Value],
+    ) -> impl Future<Result<Vec<Value>, ChangeError>> {
+        let stored = self.values_mut();
+        let mut inserted = vec![];
+        for value in values {
+            if let Entry::Vacant(entry) = stored.entry(value.key().clone()) {
+                entry.insert(value.clone());
+                inserted.push(value.clone());
+            }
+        }
+        async move { Ok(inserted) }
+    }
+
+    fn get_all(&mut self) -> impl Future<QueryResponse<Key, Value>> {
+        let values = self.values.clone();
+        async move { QueryResponse::Ok(values.into()) }
+    }
+
+    fn get_by_id(&mut self, key: Key) -> impl Future<QueryResponse<Key, Value>> {
+        let response = match self.values.get(&key) {
+            Some(value) => QueryResponse::Ok(value.clone().into()),
+            None => QueryResponse::Err(QueryError::NotPresent),
+        };
+        async move { response }
+    }
+
+    fn get_by_ids(&mut self, keys: Vec<Key>) -> impl Future<QueryResponse<Key, Value>> {
+        let response = keys
+            .iter()
+            .map(|key| self.values.get(key).cloned().ok_or(QueryError::NotPresent))
+            .collect::<Result<Vec<_>, _>>()
+            .into();
+        async move { response }
+    }
+}
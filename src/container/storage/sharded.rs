@@ -0,0 +1,285 @@
+//! [`Storage`] implementation that partitions the values over multiple
+//! storages of the same type, e.g. multiple databases.
+
+use std::{collections::HashMap, sync::Arc};
+
+use futures::future::{join_all, BoxFuture};
+use itertools::Itertools;
+
+use crate::{
+    change::{ChangeError, ChangeResult, Modifier},
+    query::{FreshData, Predicate, QueryResponse},
+    KeyBounds, ValueBounds,
+};
+
+use super::{to_boxed, Future, InitFuture, Storage};
+
+/// Decides which shard the value with the key belongs to, the returned index
+/// is taken modulo the number of shards.
+pub type ShardFn<Key> = Arc<dyn Fn(&Key) -> usize + Send + Sync>;
+
+/// Routes every change and query to the shard the keys belong to. Queries that
+/// aren't restricted to keys, like [`get_all`][Storage::get_all], are sent to
+/// every shard and the results are merged.
+///
+/// Changes spanning multiple shards are not atomic, if any shard fails the
+/// first error is returned.
+pub struct ShardedStorage<Key, Value, Shard>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Shard: Storage<Key, Value>,
+{
+    shards: Vec<Shard>,
+    shard_fn: ShardFn<Key>,
+    _marker: std::marker::PhantomData<fn() -> Value>,
+}
+
+impl<Key, Value, Shard> ShardedStorage<Key, Value, Shard>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Shard: Storage<Key, Value>,
+{
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    pub fn shards_mut(&mut self) -> &mut [Shard] {
+        &mut self.shards
+    }
+
+    fn shard_of(&self, key: &Key) -> usize {
+        (self.shard_fn)(key) % self.shards.len()
+    }
+
+    /// Splits the items into one group per shard.
+    fn split<T>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        key: impl Fn(&T) -> &Key,
+    ) -> Vec<Vec<T>> {
+        let mut groups = std::iter::repeat_with(Vec::new)
+            .take(self.shards.len())
+            .collect::<Vec<_>>();
+        for item in items {
+            groups[self.shard_of(key(&item))].push(item);
+        }
+        groups
+    }
+
+    /// Calls `f` for every shard that has a non empty group.
+    fn per_shard<T, Output>(
+        &mut self,
+        groups: Vec<Vec<T>>,
+        mut f: impl FnMut(&mut Shard, Vec<T>) -> BoxFuture<'static, Output>,
+    ) -> Vec<BoxFuture<'static, Output>> {
+        self.shards
+            .iter_mut()
+            .zip(groups)
+            .filter(|(_, group)| !group.is_empty())
+            .map(|(shard, group)| f(shard, group))
+            .collect()
+    }
+}
+
+/// The first error if there is one, otherwise the summed up affected count if
+/// every shard reported one.
+fn merge_change_results(results: Vec<ChangeResult>) -> ChangeResult {
+    let mut affected = Some(0);
+    for result in results {
+        match result {
+            ChangeResult::Error(_) => return result,
+            ChangeResult::Affected(count) => affected = affected.map(|sum| sum + count),
+            ChangeResult::Success => affected = None,
+        }
+    }
+    affected.map_or(ChangeResult::Success, ChangeResult::Affected)
+}
+
+/// The first error if there is one, otherwise the values of all shards.
+fn merge_query_responses<Key, Value>(
+    responses: Vec<QueryResponse<Key, Value>>,
+) -> QueryResponse<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    let mut values = FreshData::from(HashMap::new());
+    for response in responses {
+        match response {
+            QueryResponse::Ok(fresh_data) => values.merge(fresh_data),
+            err => return err,
+        }
+    }
+    QueryResponse::Ok(values)
+}
+
+fn join_changes(futures: Vec<BoxFuture<'static, ChangeResult>>) -> impl Future<ChangeResult> {
+    async move { merge_change_results(join_all(futures).await) }
+}
+
+fn join_queries<Key, Value>(
+    futures: Vec<BoxFuture<'static, QueryResponse<Key, Value>>>,
+) -> impl Future<QueryResponse<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    async move { merge_query_responses(join_all(futures).await) }
+}
+
+impl<Key, Value, Shard> Storage<Key, Value> for ShardedStorage<Key, Value, Shard>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+    Shard: Storage<Key, Value>,
+    Shard::InitArgs: Send + 'static,
+{
+    /// The init arguments of every shard and the function deciding which
+    /// shard a key belongs to.
+    type InitArgs = (Vec<Shard::InitArgs>, ShardFn<Key>);
+
+    fn init((shard_args, shard_fn): Self::InitArgs) -> impl InitFuture<Self> {
+        async move {
+            assert!(!shard_args.is_empty(), "A sharded storage needs at least one shard.");
+            let mut shards = Vec::with_capacity(shard_args.len());
+            for args in shard_args {
+                shards.push(Shard::init(args).await);
+            }
+            Self {
+                shards,
+                shard_fn,
+                _marker: std::marker::PhantomData,
+            }
+        }
+    }
+
+    /// Migrates every shard, the first error is returned.
+    fn migrate(&mut self) -> impl Future<Result<(), ChangeError>> {
+        let futures = self.shards.iter_mut().map(|shard| to_boxed(shard.migrate())).collect_vec();
+        async move { join_all(futures).await.into_iter().collect() }
+    }
+
+    fn insert(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        let shard = self.shard_of(value.key());
+        to_boxed(self.shards[shard].insert(value))
+    }
+
+    fn insert_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        let groups = self.split(values.iter().cloned(), |value| value.key());
+        join_changes(self.per_shard(groups, |shard, group| to_boxed(shard.insert_many(&group))))
+    }
+
+    fn update(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        let shard = self.shard_of(value.key());
+        to_boxed(self.shards[shard].update(value))
+    }
+
+    fn update_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        let groups = self.split(values.iter().cloned(), |value| value.key());
+        join_changes(self.per_shard(groups, |shard, group| to_boxed(shard.update_many(&group))))
+    }
+
+    fn delete(&mut self, key: &Key) -> impl Future<ChangeResult> {
+        let shard = self.shard_of(key);
+        to_boxed(self.shards[shard].delete(key))
+    }
+
+    /// Only the shard of `key` is asked, the cascading keys are then deleted
+    /// from whichever shard they are in.
+    fn on_delete_cascade(&mut self, key: &Key) -> impl Future<Vec<Key>> {
+        let shard = self.shard_of(key);
+        to_boxed(self.shards[shard].on_delete_cascade(key))
+    }
+
+    fn delete_many(&mut self, keys: &[Key]) -> impl Future<ChangeResult> {
+        let groups = self.split(keys.iter().cloned(), |key| key);
+        join_changes(self.per_shard(groups, |shard, group| to_boxed(shard.delete_many(&group))))
+    }
+
+    /// Only the shard of `key` is asked, so `before` and `after` are only
+    /// known to it if they are in the same shard.
+    fn reorder(
+        &mut self,
+        key: &Key,
+        before: Option<&Key>,
+        after: Option<&Key>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let shard = self.shard_of(key);
+        to_boxed(self.shards[shard].reorder(key, before, after))
+    }
+
+    /// Moving a value into another shard isn't atomic, so `old` and `new`
+    /// have to belong to the same shard.
+    fn rekey(
+        &mut self,
+        old: &Key,
+        new: &Key,
+        set_key: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let shard = self.shard_of(old);
+        if shard != self.shard_of(new) {
+            let unsupported = ChangeError::Unsupported(String::from("rekey across shards"));
+            return to_boxed(async move { Err(unsupported) });
+        }
+        to_boxed(self.shards[shard].rekey(old, new, set_key))
+    }
+
+    fn modify(
+        &mut self,
+        key: &Key,
+        f: Modifier<Value>,
+    ) -> impl Future<Result<Value, ChangeError>> {
+        let shard = self.shard_of(key);
+        to_boxed(self.shards[shard].modify(key, f))
+    }
+
+    fn insert_if_absent_many(
+        &mut self,
+        values: &[Value],
+    ) -> impl Future<Result<Vec<Value>, ChangeError>> {
+        let groups = self.split(values.iter().cloned(), |value| value.key());
+        let futures = self.per_shard(groups, |shard, group| {
+            to_boxed(shard.insert_if_absent_many(&group))
+        });
+        async move {
+            join_all(futures)
+                .await
+                .into_iter()
+                .flatten_ok()
+                .collect::<Result<Vec<_>, _>>()
+        }
+    }
+
+    fn get_all(&mut self) -> impl Future<QueryResponse<Key, Value>> {
+        let futures = self
+            .shards
+            .iter_mut()
+            .map(|shard| to_boxed(shard.get_all()))
+            .collect();
+        join_queries(futures)
+    }
+
+    fn get_by_id(&mut self, key: Key) -> impl Future<QueryResponse<Key, Value>> {
+        let shard = self.shard_of(&key);
+        to_boxed(self.shards[shard].get_by_id(key))
+    }
+
+    fn get_by_ids(&mut self, keys: Vec<Key>) -> impl Future<QueryResponse<Key, Value>> {
+        let groups = self.split(keys, |key| key);
+        join_queries(self.per_shard(groups, |shard, group| to_boxed(shard.get_by_ids(group))))
+    }
+
+    fn get_by_predicate(
+        &mut self,
+        predicate: Predicate<Value>,
+    ) -> impl Future<QueryResponse<Key, Value>> {
+        let futures = self
+            .shards
+            .iter_mut()
+            .map(|shard| to_boxed(shard.get_by_predicate(predicate.clone())))
+            .collect();
+        join_queries(futures)
+    }
+}
@@ -0,0 +1,325 @@
+//! [`Storage`] implementation on top of a [`sqlx`] connection [`Pool`], only
+//! available with the `sqlx` feature. The `sqlite` feature additionally
+//! enables the SQLite driver of [`sqlx`] and its tokio runtime.
+//!
+//! The SQL itself is generated from the [`SqlxTable`] description of the
+//! `Value`, which also decides how the `Value` is read from a row.
+
+use std::marker::PhantomData;
+
+use sqlx::{
+    error::BoxDynError, Arguments, Database, Encode, Executor, FromRow, IntoArguments, Pool, Type,
+};
+
+use crate::{
+    change::{ChangeError, ChangeResult},
+    query::{QueryError, QueryResponse},
+    KeyBounds, ValueBounds,
+};
+
+use super::{Future, InitFuture, Storage, StorageCapabilities};
+
+/// Describes the table a `Value` is stored in. Reading a `Value` from a row is
+/// done through [`FromRow`].
+pub trait SqlxTable<Key, DB>
+where
+    Self: for<'r> FromRow<'r, DB::Row>,
+    DB: Database,
+{
+    /// Name of the table.
+    const TABLE: &'static str;
+    /// Name of the column holding the key.
+    const KEY_COLUMN: &'static str;
+    /// Every column that is written, including the key column.
+    const COLUMNS: &'static [&'static str];
+
+    /// Binds the key with [`push_bind`][SqlBuilder::push_bind].
+    fn push_key<'args>(key: &Key, query: &mut SqlBuilder<'args, DB>);
+    /// Binds the value of `column` with [`push_bind`][SqlBuilder::push_bind].
+    fn push_column<'args>(&self, column: &str, query: &mut SqlBuilder<'args, DB>);
+}
+
+/// SQL statement together with the arguments bound to it. Placeholders are
+/// written in the format of the database.
+pub struct SqlBuilder<'args, DB>
+where
+    DB: Database,
+{
+    sql: String,
+    arguments: DB::Arguments<'args>,
+    error: Option<BoxDynError>,
+}
+
+impl<'args, DB> SqlBuilder<'args, DB>
+where
+    DB: Database,
+{
+    fn new(sql: impl Into<String>) -> Self {
+        Self {
+            sql: sql.into(),
+            arguments: DB::Arguments::default(),
+            error: None,
+        }
+    }
+
+    /// Appends plain SQL.
+    pub fn push(&mut self, sql: impl AsRef<str>) -> &mut Self {
+        self.sql.push_str(sql.as_ref());
+        self
+    }
+
+    /// Binds the value and appends a placeholder for it.
+    pub fn push_bind<T>(&mut self, value: T) -> &mut Self
+    where
+        T: 'args + Encode<'args, DB> + Type<DB>,
+    {
+        if let Err(err) = self.arguments.add(value) {
+            self.error.get_or_insert(err);
+        }
+        if let Err(err) = self.arguments.format_placeholder(&mut self.sql) {
+            self.error.get_or_insert(Box::new(err));
+        }
+        self
+    }
+
+    fn into_parts(self) -> Result<(String, DB::Arguments<'args>), sqlx::Error> {
+        match self.error {
+            Some(err) => Err(sqlx::Error::Encode(err)),
+            None => Ok((self.sql, self.arguments)),
+        }
+    }
+}
+
+/// Stores the values in the table described by [`SqlxTable`]. Initialized
+/// with the [`Pool`] it will run all of its queries on.
+pub struct SqlxStorage<Key, Value, DB>
+where
+    DB: Database,
+{
+    pool: Pool<DB>,
+    _marker: PhantomData<fn() -> (Key, Value)>,
+}
+
+/// Owned description of a single SQL statement. The actual [`SqlBuilder`]
+/// is only created inside of the returned futures since the arguments it
+/// binds borrow the SQL.
+enum Statement<Key, Value> {
+    Insert(Vec<Value>),
+    Update(Value),
+    Delete(Vec<Key>),
+    SelectAll,
+    Select(Vec<Key>),
+}
+
+impl<Key, Value> Statement<Key, Value> {
+    /// Wether the statement can't affect or return any row, e.g. a delete
+    /// without keys. Running those would produce invalid SQL like `IN ()`.
+    fn is_noop<DB>(&self) -> bool
+    where
+        Value: SqlxTable<Key, DB>,
+        DB: Database,
+    {
+        match self {
+            Self::Insert(values) => values.is_empty(),
+            // NOTE: without any other column there is nothing to `SET`
+            Self::Update(_) => Value::COLUMNS
+                .iter()
+                .all(|column| *column == Value::KEY_COLUMN),
+            Self::Delete(keys) | Self::Select(keys) => keys.is_empty(),
+            Self::SelectAll => false,
+        }
+    }
+
+    fn build<'args, DB>(&self) -> SqlBuilder<'args, DB>
+    where
+        Value: SqlxTable<Key, DB> + crate::GetKey<Key>,
+        DB: Database,
+    {
+        match self {
+            Self::Insert(values) => {
+                let mut query = SqlBuilder::new(format!(
+                    "INSERT INTO {} ({}) VALUES ",
+                    Value::TABLE,
+                    Value::COLUMNS.join(", ")
+                ));
+                for (row, value) in values.iter().enumerate() {
+                    query.push(if row > 0 { ", (" } else { "(" });
+                    for (index, column) in Value::COLUMNS.iter().enumerate() {
+                        if index > 0 {
+                            query.push(", ");
+                        }
+                        value.push_column(column, &mut query);
+                    }
+                    query.push(")");
+                }
+                query
+            }
+            Self::Update(value) => {
+                let mut query = SqlBuilder::new(format!("UPDATE {} SET ", Value::TABLE));
+                let columns = Value::COLUMNS
+                    .iter()
+                    .filter(|column| **column != Value::KEY_COLUMN);
+                for (index, column) in columns.enumerate() {
+                    if index > 0 {
+                        query.push(", ");
+                    }
+                    query.push(format!("{column} = "));
+                    value.push_column(column, &mut query);
+                }
+                query.push(format!(" WHERE {} = ", Value::KEY_COLUMN));
+                Value::push_key(value.key(), &mut query);
+                query
+            }
+            Self::Delete(keys) => Self::keys_query("DELETE FROM", keys),
+            Self::SelectAll => SqlBuilder::new(format!("SELECT * FROM {}", Value::TABLE)),
+            Self::Select(keys) => Self::keys_query("SELECT * FROM", keys),
+        }
+    }
+
+    /// Query with `statement` followed by a `WHERE` clause matching all `keys`.
+    fn keys_query<'args, DB>(statement: &str, keys: &[Key]) -> SqlBuilder<'args, DB>
+    where
+        Value: SqlxTable<Key, DB>,
+        DB: Database,
+    {
+        let mut query = SqlBuilder::new(format!(
+            "{statement} {} WHERE {} IN (",
+            Value::TABLE,
+            Value::KEY_COLUMN
+        ));
+        for (index, key) in keys.iter().enumerate() {
+            if index > 0 {
+                query.push(", ");
+            }
+            Value::push_key(key, &mut query);
+        }
+        query.push(")");
+        query
+    }
+}
+
+impl<Key, Value, DB> SqlxStorage<Key, Value, DB>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key> + SqlxTable<Key, DB> + Unpin,
+    DB: Database,
+    for<'c> &'c Pool<DB>: Executor<'c, Database = DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+{
+    /// Runs all statements inside of a single transaction.
+    fn execute(&self, mut statements: Vec<Statement<Key, Value>>) -> impl Future<ChangeResult> {
+        statements.retain(|statement| !statement.is_noop::<DB>());
+        let pool = self.pool.clone();
+        async move {
+            if statements.is_empty() {
+                return ChangeResult::Success;
+            }
+            let result = async {
+                let mut transaction = pool.begin().await?;
+                for statement in &statements {
+                    let (sql, arguments) = statement.build::<DB>().into_parts()?;
+                    sqlx::query_with(&sql, arguments)
+                        .execute(&mut *transaction)
+                        .await?;
+                }
+                transaction.commit().await
+            };
+            match result.await {
+                Ok(()) => ChangeResult::Success,
+                Err(err) => ChangeResult::Error(ChangeError::DatabaseError(err.to_string())),
+            }
+        }
+    }
+
+    fn fetch(&self, statement: Statement<Key, Value>) -> impl Future<QueryResponse<Key, Value>> {
+        let pool = self.pool.clone();
+        async move {
+            if statement.is_noop::<DB>() {
+                return QueryResponse::Ok(Vec::new().into());
+            }
+            let values = async {
+                let (sql, arguments) = statement.build::<DB>().into_parts()?;
+                sqlx::query_as_with::<_, Value, _>(&sql, arguments)
+                    .fetch_all(&pool)
+                    .await
+            };
+            match values.await {
+                Ok(values) => QueryResponse::Ok(values.into()),
+                Err(err) => QueryResponse::Err(QueryError::Backend(err.to_string())),
+            }
+        }
+    }
+}
+
+impl<Key, Value, DB> Storage<Key, Value> for SqlxStorage<Key, Value, DB>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key> + SqlxTable<Key, DB> + Unpin,
+    DB: Database,
+    for<'c> &'c Pool<DB>: Executor<'c, Database = DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+    for<'q> DB::Arguments<'q>: IntoArguments<'q, DB>,
+{
+    type InitArgs = Pool<DB>;
+
+    fn init(pool: Self::InitArgs) -> impl InitFuture<Self> {
+        async move {
+            Self {
+                pool,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    fn capabilities(&self) -> StorageCapabilities {
+        StorageCapabilities {
+            transactions: true,
+            ..StorageCapabilities::default()
+        }
+    }
+
+    fn insert(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        self.execute(vec![Statement::Insert(vec![value.clone()])])
+    }
+
+    fn insert_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        self.execute(vec![Statement::Insert(values.to_vec())])
+    }
+
+    fn update(&mut self, value: &Value) -> impl Future<ChangeResult> {
+        self.execute(vec![Statement::Update(value.clone())])
+    }
+
+    fn update_many(&mut self, values: &[Value]) -> impl Future<ChangeResult> {
+        self.execute(values.iter().cloned().map(Statement::Update).collect())
+    }
+
+    fn delete(&mut self, key: &Key) -> impl Future<ChangeResult> {
+        self.execute(vec![Statement::Delete(vec![key.clone()])])
+    }
+
+    fn delete_many(&mut self, keys: &[Key]) -> impl Future<ChangeResult> {
+        self.execute(vec![Statement::Delete(keys.to_vec())])
+    }
+
+    fn get_all(&mut self) -> impl Future<QueryResponse<Key, Value>> {
+        self.fetch(Statement::SelectAll)
+    }
+
+    fn get_by_id(&mut self, key: Key) -> impl Future<QueryResponse<Key, Value>> {
+        let response = self.fetch(Statement::Select(vec![key]));
+        async move {
+            match response.await {
+                QueryResponse::Ok(values) if values.is_empty() => {
+                    QueryResponse::Err(QueryError::NotPresent)
+                }
+                response => response,
+            }
+        }
+    }
+
+    fn get_by_ids(&mut self, keys: Vec<Key>) -> impl Future<QueryResponse<Key, Value>> {
+        self.fetch(Statement::Select(keys))
+    }
+}
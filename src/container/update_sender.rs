@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use itertools::Itertools;
 use lazy_async_promise::{BoxedSendError, ImmediateValuePromise, ImmediateValueState};
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::{self, error::TrySendError};
 use tracing::{debug, enabled, trace, Level};
 use uuid::Uuid;
 
@@ -10,14 +10,114 @@ use crate::{
     change::DataChange, query::FreshData, utils::DrainIf, KeyBounds, ValueBounds
 };
 
+use super::Signal;
+
+/// Capacity the change channel of every new communicator starts out with.
+pub const INITIAL_CHANGE_CAPACITY: usize = 20;
+/// Default for [`set_max_change_capacity`][UpdateSender::set_max_change_capacity].
+const DEFAULT_MAX_CHANGE_CAPACITY: usize = 8 * INITIAL_CHANGE_CAPACITY;
+/// How many changes have to find a channel full before it is grown.
+const GROW_AFTER_FULL: usize = 3;
+
+pub type GrownReciverSender<Key, Value> =
+    mpsc::UnboundedSender<mpsc::Receiver<DataChange<Key, Value>>>;
+
+/// Change channel to a single communicator.
+///
+/// Since the capacity of a channel is fixed, growing it means creating a new
+/// channel and handing its reciver to the communicator. The communicator keeps
+/// reading from the old reciver until every change that was still in flight on
+/// it arrived, so that the order of the changes is kept.
+struct ChangeChannel<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    sender: mpsc::Sender<DataChange<Key, Value>>,
+    grown_reciver_sender: GrownReciverSender<Key, Value>,
+    capacity: usize,
+    times_full: usize,
+    /// Changes held back while the channel is full at the maximum capacity,
+    /// merged and sent once the communicator made room again.
+    held_back: Vec<DataChange<Key, Value>>,
+}
+
+impl<Key, Value> ChangeChannel<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    /// Replaces the channel with one of twice the capacity, but at most `max`.
+    fn grow(&mut self, cont_uuid: &Uuid, target: &Uuid, max: usize) {
+        let capacity = (self.capacity * 2).min(max);
+        let (sender, reciver) = mpsc::channel(capacity);
+        if self.grown_reciver_sender.send(reciver).is_err() {
+            return;
+        }
+        debug!(
+            msg = format!(
+                "Grew change channel to communicator [{target}] from {} to {capacity}.",
+                self.capacity
+            ),
+            cont = cont_uuid.to_string()
+        );
+        self.sender = sender;
+        self.capacity = capacity;
+        self.times_full = 0;
+    }
+
+    /// Sends as many of the held back changes as fit into the channel.
+    fn send_held_back(&mut self) {
+        let mut held_back = coalesce(std::mem::take(&mut self.held_back)).into_iter();
+        while let Some(change) = held_back.next() {
+            match self.sender.try_send(change) {
+                Ok(()) => (),
+                Err(TrySendError::Full(change)) => {
+                    self.held_back = std::iter::once(change).chain(held_back).collect();
+                    return;
+                }
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+    }
+}
+
+/// Merges neighbouring changes of the same kind into a single change.
+fn coalesce<Key, Value>(changes: Vec<DataChange<Key, Value>>) -> Vec<DataChange<Key, Value>>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    changes
+        .into_iter()
+        .coalesce(|first, second| match (first, second) {
+            (DataChange::Insert(mut first), DataChange::Insert(second)) => {
+                first.extend(second);
+                Ok(DataChange::Insert(first))
+            }
+            (DataChange::Update(mut first), DataChange::Update(second)) => {
+                first.extend(second);
+                Ok(DataChange::Update(first))
+            }
+            (DataChange::Delete(mut first), DataChange::Delete(second)) => {
+                first.extend(second);
+                Ok(DataChange::Delete(first))
+            }
+            (first, second) => Err((first, second)),
+        })
+        .collect_vec()
+}
+
 pub struct UpdateSender<Key, Value>
 where
     Key: KeyBounds,
     Value: ValueBounds<Key>,
 {
-    change_senders: HashMap<Uuid, mpsc::Sender<DataChange<Key, Value>>>,
+    change_senders: HashMap<Uuid, ChangeChannel<Key, Value>>,
     query_senders: HashMap<Uuid, mpsc::Sender<FreshData<Key, Value>>>,
+    signal_senders: HashMap<Uuid, mpsc::UnboundedSender<Signal>>,
     sending_responses: Vec<ImmediateValuePromise<()>>,
+    max_change_capacity: usize,
 }
 impl<Key, Value> Default for UpdateSender<Key, Value>
 where
@@ -28,7 +128,9 @@ where
         Self {
             change_senders: HashMap::new(),
             query_senders: HashMap::new(),
+            signal_senders: HashMap::new(),
             sending_responses: vec![],
+            max_change_capacity: DEFAULT_MAX_CHANGE_CAPACITY,
         }
     }
 }
@@ -44,18 +146,82 @@ where
         &mut self,
         communicator_uuid: &Uuid,
         change_sender: mpsc::Sender<DataChange<Key, Value>>,
+        grown_reciver_sender: GrownReciverSender<Key, Value>,
         query_sender: mpsc::Sender<FreshData<Key, Value>>,
+        signal_sender: mpsc::UnboundedSender<Signal>,
     ) {
+        let change_channel = ChangeChannel {
+            capacity: change_sender.max_capacity(),
+            sender: change_sender,
+            grown_reciver_sender,
+            times_full: 0,
+            held_back: vec![],
+        };
         let existing_change_sender = self
             .change_senders
-            .insert(*communicator_uuid, change_sender);
+            .insert(*communicator_uuid, change_channel);
         assert!(existing_change_sender.is_none());
 
         let existing_query_sender = self.query_senders.insert(*communicator_uuid, query_sender);
         assert!(existing_query_sender.is_none());
+
+        let existing_signal_sender = self.signal_senders.insert(*communicator_uuid, signal_sender);
+        assert!(existing_signal_sender.is_none());
+    }
+
+    /// Removes the senders of a communicator again.
+    pub fn unregister_senders(&mut self, communicator_uuid: &Uuid) {
+        self.change_senders.remove(communicator_uuid);
+        self.query_senders.remove(communicator_uuid);
+        self.signal_senders.remove(communicator_uuid);
+    }
+
+    /// Wether the communicator was dropped, which closes its recivers.
+    pub fn is_closed(&self, communicator_uuid: &Uuid) -> bool {
+        self.query_senders
+            .get(communicator_uuid)
+            .is_none_or(mpsc::Sender::is_closed)
+    }
+
+    /// Communicators that were dropped, which closes their recivers.
+    pub fn closed_communicators(&self) -> Vec<Uuid> {
+        self.query_senders
+            .iter()
+            .filter(|(_, sender)| sender.is_closed())
+            .map(|(uuid, _)| *uuid)
+            .collect_vec()
+    }
+
+    /// Change channels that repeatedly are full are grown up to `max`, once
+    /// they reach it further changes are merged until there is room again.
+    pub fn set_max_change_capacity(&mut self, max: usize) {
+        self.max_change_capacity = max;
+    }
+
+    /// Current capacity of the change channel to the communicator.
+    pub fn change_capacity(&self, communicator_uuid: &Uuid) -> Option<usize> {
+        self.change_senders
+            .get(communicator_uuid)
+            .map(|channel| channel.capacity)
+    }
+
+    /// Changes held back for the communicator until its channel has room.
+    pub fn held_back_changes(&self, communicator_uuid: &Uuid) -> usize {
+        self.change_senders
+            .get(communicator_uuid)
+            .map_or(0, |channel| channel.held_back.len())
+    }
+
+    /// Changes and fresh data that are still being sent to communicators.
+    pub fn pending_sends(&self) -> usize {
+        self.sending_responses.len()
     }
 
     pub fn state_update(&mut self) {
+        self.change_senders
+            .values_mut()
+            .filter(|channel| !channel.held_back.is_empty())
+            .for_each(ChangeChannel::send_held_back);
         let _ = self
             .sending_responses
             .drain_if(|e| !matches!(e.poll_state(), ImmediateValueState::Updating));
@@ -72,17 +238,37 @@ where
 
         let new_sending_responses = targets
             .into_iter()
-            .map(|(target, change)| {
-                let sender = self.change_senders.get(&target).unwrap().clone();
+            .filter_map(|(target, change)| {
+                let channel = self.change_senders.get_mut(&target).unwrap();
+                if !channel.held_back.is_empty() {
+                    channel.held_back.push(change);
+                    return None;
+                }
+                if channel.sender.capacity() == 0 {
+                    channel.times_full += 1;
+                    if channel.capacity >= self.max_change_capacity {
+                        trace!(
+                            msg = format!("Holding back change to full communicator [{target}]."),
+                            cont = cont_uuid.to_string()
+                        );
+                        channel.held_back.push(change);
+                        return None;
+                    }
+                    if channel.times_full >= GROW_AFTER_FULL {
+                        channel.grow(cont_uuid, &target, self.max_change_capacity);
+                    }
+                }
+                let sender = channel.sender.clone();
                 let string_uuid = cont_uuid.to_string();
-                ImmediateValuePromise::new(async move {
+                let promise = ImmediateValuePromise::new(async move {
                     let send_res = sender.send(change).await.map_err(BoxedSendError::from);
                     debug!(
                         msg = format!("Sent off data change to communicator [{target}]."),
                         cont = string_uuid
                     );
                     send_res
-                })
+                });
+                Some(promise)
             })
             .collect_vec();
 
@@ -98,6 +284,19 @@ where
         }
     }
 
+    /// Sends the signal to every communicator that wasn't dropped yet.
+    pub fn send_signal(&mut self, cont_uuid: &Uuid, signal: Signal) {
+        trace!(
+            msg = format!(
+                "Sending signal [{signal:?}] to {} communicators",
+                self.signal_senders.len()
+            ),
+            cont = cont_uuid.to_string()
+        );
+        self.signal_senders
+            .retain(|_, signal_sender| signal_sender.send(signal).is_ok());
+    }
+
     /// Returns fresh data to the communicator that requested the data.
     pub fn send_fresh_data(
         &mut self,
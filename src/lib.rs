@@ -113,6 +113,26 @@ impl<Key> GetKey<Key> for Key {
     }
 }
 
+/// Implemented by values whose key can change, which allows to move them to a
+/// new key with [`Communicator::rekey`][communicator::Communicator::rekey].
+pub trait SetKey<Key>: GetKey<Key> {
+    fn set_key(&mut self, key: Key);
+}
+
+impl<Key> SetKey<Key> for Key {
+    fn set_key(&mut self, key: Key) {
+        *self = key;
+    }
+}
+
+/// Implemented by values that carry a version, which allows to only query the
+/// values that changed since a given version with
+/// [`QueryType::changed_since`][query::QueryType::changed_since].
+pub trait Versioned {
+    type Cursor: Ord + Clone + Send + Sync + 'static;
+    fn version(&self) -> Self::Cursor;
+}
+
 
 pub(crate) trait GetKeys<Key> {
     fn keys(&self) -> Vec<&Key>;
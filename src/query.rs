@@ -1,6 +1,14 @@
 //! Contains all of the structs related to query requests, responses and more.
 
-use std::{collections::HashMap, error::Error, fmt::Display, ops::{Deref, DerefMut}, sync::Arc};
+use std::{
+    any::Any,
+    cmp::Ordering,
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
 
 use tokio::sync::{
     mpsc,
@@ -8,7 +16,7 @@ use tokio::sync::{
 };
 use uuid::Uuid;
 
-use super::{KeyBounds, ValueBounds};
+use super::{KeyBounds, ValueBounds, Versioned};
 
 pub(crate) struct DataQuery<Key, Value>
 where
@@ -17,7 +25,14 @@ where
 {
     pub origin_uuid: Uuid,
     pub response_sender: oneshot::Sender<QueryResult>,
+    pub fresh_data_sender: Option<oneshot::Sender<FreshData<Key, Value>>>,
     pub query_type: QueryType<Key, Value>,
+    /// Set by communicators that track their queries, it is passed back along
+    /// with the resulting [`FreshData`].
+    pub query_id: Option<u64>,
+    /// The result is returned in chunks, see
+    /// [`Storage::query_streamed`][crate::container::storage::Storage::query_streamed].
+    pub streamed: bool,
 }
 
 impl<Key, Value> DataQuery<Key, Value>
@@ -34,14 +49,106 @@ where
             Self {
                 origin_uuid,
                 response_sender: sender,
+                fresh_data_sender: None,
                 query_type,
+                query_id: None,
+                streamed: false,
             },
             reciver,
         )
     }
+
+    /// Same as [`from_type`][DataQuery::from_type] but will additionally
+    /// return a copy of the resulting [`FreshData`] through the second reciver.
+    pub fn fetching_from_type(
+        origin_uuid: Uuid,
+        query_type: QueryType<Key, Value>,
+    ) -> (
+        Self,
+        oneshot::Receiver<QueryResult>,
+        oneshot::Receiver<FreshData<Key, Value>>,
+    ) {
+        let (mut query, reciver) = Self::from_type(origin_uuid, query_type);
+        let (fresh_data_sender, fresh_data_reciver) = oneshot::channel();
+        query.fresh_data_sender = Some(fresh_data_sender);
+        (query, reciver, fresh_data_reciver)
+    }
+}
+
+/// Queries with [`Priority::High`] are recived by the container before any
+/// query with [`Priority::Normal`], regardless of when they were sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
 }
 
 pub type Predicate<Value> = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+/// Names the group of a value for a [`QueryType::GroupCount`] query.
+pub type GroupFn<Value> = Arc<dyn Fn(&Value) -> String + Send + Sync>;
+
+/// Order of a [`QueryType::Latest`] query. The `name` identifies the order
+/// for storages that can't run the comparison themselves, e.g. a database
+/// can map it to the column of an `ORDER BY`.
+#[derive(Clone)]
+pub struct SortKey<Value> {
+    name: &'static str,
+    cmp: SortFn<Value>,
+}
+
+type SortFn<Value> = Arc<dyn Fn(&Value, &Value) -> Ordering + Send + Sync>;
+
+impl<Value> SortKey<Value> {
+    pub fn new(
+        name: &'static str,
+        cmp: impl Fn(&Value, &Value) -> Ordering + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name,
+            cmp: Arc::new(cmp),
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+    pub fn compare(&self, first: &Value, second: &Value) -> Ordering {
+        (self.cmp)(first, second)
+    }
+}
+
+/// Cursor of a [`QueryType::ChangedSince`] query. The cursor itself is type
+/// erased so that the [`QueryType`] doesn't need `Value` to be [`Versioned`],
+/// storages that know their `Value` can get it back with [`Since::cursor`].
+#[derive(Clone)]
+pub struct Since<Value> {
+    cursor: Arc<dyn Any + Send + Sync>,
+    is_newer: Predicate<Value>,
+}
+
+impl<Value> Since<Value> {
+    pub fn new(cursor: Value::Cursor) -> Self
+    where
+        Value: Versioned,
+    {
+        let compare_cursor = cursor.clone();
+        Self {
+            cursor: Arc::new(cursor),
+            is_newer: Arc::new(move |value: &Value| value.version() > compare_cursor),
+        }
+    }
+    /// Returns the cursor if `Cursor` is the cursor type of the `Value`.
+    pub fn cursor<Cursor: 'static>(&self) -> Option<&Cursor> {
+        self.cursor.downcast_ref()
+    }
+    /// Wether the value changed after the cursor.
+    pub fn is_newer(&self, value: &Value) -> bool {
+        (self.is_newer)(value)
+    }
+    pub fn predicate(&self) -> Predicate<Value> {
+        self.is_newer.clone()
+    }
+}
 
 #[derive(Clone)]
 pub enum QueryType<Key, Value>
@@ -53,6 +160,26 @@ where
     GetById(Key),
     GetByIds(Vec<Key>),
     Predicate(Predicate<Value>),
+    /// Matches every value the inner query does not match.
+    Not(Box<QueryType<Key, Value>>),
+    /// Matches every value that changed after the cursor, see [`Versioned`].
+    ChangedSince(Since<Value>),
+    /// Matches the values with one of the `base_keys` that match the predicate,
+    /// e.g. to narrow down the values a communicator already holds.
+    Intersect {
+        base_keys: Vec<Key>,
+        pred: Predicate<Value>,
+    },
+    /// Counts the values per group instead of returning them, the result is
+    /// a [`QueryResult::Groups`]. The communicator doesn't recive any values,
+    /// so it can't be negated with a `Not` or used to prune a communicator.
+    GroupCount { group_by: GroupFn<Value> },
+    /// Matches the `limit` greatest values under `by`, e.g. the latest 50.
+    /// Whether a value matches depends on all other values, so it can't be
+    /// negated with a `Not` or used to prune a communicator. A communicator
+    /// whose last query was this repeats it after every insert instead, so
+    /// that newer values still reach it.
+    Latest { by: SortKey<Value>, limit: usize },
 }
 
 impl<Key, Value> QueryType<Key, Value>
@@ -65,7 +192,33 @@ where
             Self::All => true,
             Self::GetById(key) => key.eq(value.key()),
             Self::GetByIds(keys) => keys.contains(value.key()),
-            Self::Predicate(predicate) => predicate(value)
+            Self::Predicate(predicate) => predicate(value),
+            Self::Not(query) => !query.apply(value),
+            Self::ChangedSince(since) => since.is_newer(value),
+            Self::Intersect { base_keys, pred } => base_keys.contains(value.key()) && pred(value),
+            Self::GroupCount { .. } | Self::Latest { .. } => false,
+        }
+    }
+
+    /// Wether the query can't match any value, without asking the storage.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::GetByIds(keys) => keys.is_empty(),
+            Self::Intersect { base_keys, .. } => base_keys.is_empty(),
+            Self::Latest { limit, .. } => *limit == 0,
+            _ => false,
+        }
+    }
+
+    /// Wether [`apply`][QueryType::apply] can tell if a single value matches,
+    /// which isn't the case for queries that depend on all other values, like
+    /// [`Latest`][QueryType::Latest], or don't return values at all, like
+    /// [`GroupCount`][QueryType::GroupCount].
+    pub fn matches_per_value(&self) -> bool {
+        match self {
+            Self::Not(query) => query.matches_per_value(),
+            Self::GroupCount { .. } | Self::Latest { .. } => false,
+            _ => true,
         }
     }
 
@@ -82,6 +235,11 @@ where
             Self::GetById(_) => String::from("GetById"),
             Self::GetByIds(vals) => format!("GetByIds({})", vals.len()),
             Self::Predicate(_) => String::from("Predicate"),
+            Self::Not(query) => format!("Not({query})"),
+            Self::ChangedSince(_) => String::from("ChangedSince"),
+            Self::Intersect { base_keys, .. } => format!("Intersect({})", base_keys.len()),
+            Self::GroupCount { .. } => String::from("GroupCount"),
+            Self::Latest { by, limit } => format!("Latest({}, {limit})", by.name()),
         })
     }
 }
@@ -96,6 +254,26 @@ where
     pub fn predicate<T: Fn(&Value) -> bool + Send + Sync +'static>(pred: T) -> Self {
         Self::Predicate(Arc::new(pred))
     }
+    pub fn intersect<T: Fn(&Value) -> bool + Send + Sync + 'static>(
+        base_keys: Vec<Key>,
+        pred: T,
+    ) -> Self {
+        Self::Intersect {
+            base_keys,
+            pred: Arc::new(pred),
+        }
+    }
+    pub fn changed_since(cursor: Value::Cursor) -> Self
+    where
+        Value: Versioned,
+    {
+        Self::ChangedSince(Since::new(cursor))
+    }
+    pub fn group_count<T: Fn(&Value) -> String + Send + Sync + 'static>(group_by: T) -> Self {
+        Self::GroupCount {
+            group_by: Arc::new(group_by),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -108,6 +286,8 @@ where
     // but the compiler doesnt allow me to keep the Key generic If I dont use it.
     // Same problem as this one: https://internals.rust-lang.org/t/type-parameter-not-used-on-enums/13342
     Ok(FreshData<Key, Value>),
+    /// Number of values per group, the response to a [`QueryType::GroupCount`].
+    Groups(HashMap<String, usize>),
     Err(QueryError),
 }
 
@@ -133,7 +313,14 @@ where
 {
     fn from(value: QueryResponse<Key, Value>) -> Self {
         match value {
-            QueryResponse::Ok(fresh_data) => (Some(fresh_data), QueryResult::Success),
+            QueryResponse::Ok(fresh_data) => {
+                let result = match fresh_data.total {
+                    Some(total) => QueryResult::Page { total: Some(total) },
+                    None => QueryResult::Success,
+                };
+                (Some(fresh_data), result)
+            }
+            QueryResponse::Groups(groups) => (None, QueryResult::Groups(groups)),
             QueryResponse::Err(err) => (None, QueryResult::Error(err)),
         }
     }
@@ -142,6 +329,11 @@ where
 #[derive(Clone, Debug)]
 pub enum QueryResult {
     Success,
+    /// Success of a query that only returned a part of the matching values,
+    /// `total` is the number of values the storage holds overall.
+    Page { total: Option<usize> },
+    /// Success of a [`QueryType::GroupCount`] query.
+    Groups(HashMap<String, usize>),
     Error(QueryError),
 }
 
@@ -152,6 +344,23 @@ pub enum QueryError {
     ChannelSend(String),
     ChannelTrySend(String),
     ChannelRecive(RecvError),
+    /// The storage backend itself failed.
+    Backend(String),
+    /// The query returned more values than the container allows, see
+    /// [`set_max_query_result_size`][crate::container::DataContainer::set_max_query_result_size].
+    ResultTooLarge { size: usize, max: usize },
+    /// The query can't be answered, e.g. a `Not` of a [`QueryType::Latest`].
+    Unsupported(String),
+}
+
+impl QueryResult {
+    /// Every result but [`Error`][QueryResult::Error] is `Ok`.
+    pub fn into_result(self) -> Result<(), QueryError> {
+        match self {
+            Self::Success | Self::Page { .. } | Self::Groups(_) => Ok(()),
+            Self::Error(err) => Err(err),
+        }
+    }
 }
 
 impl QueryError {
@@ -171,6 +380,25 @@ impl Display for QueryError {
 }
 impl Error for QueryError {}
 
+/// So that storages can use `?` on their backend's errors.
+impl From<std::io::Error> for QueryError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Backend(value.to_string())
+    }
+}
+
+impl From<Box<dyn Error>> for QueryError {
+    fn from(value: Box<dyn Error>) -> Self {
+        Self::Backend(value.to_string())
+    }
+}
+
+impl From<Box<dyn Error + Send + Sync>> for QueryError {
+    fn from(value: Box<dyn Error + Send + Sync>) -> Self {
+        Self::Backend(value.to_string())
+    }
+}
+
 impl From<Result<QueryResult, RecvError>> for QueryResult {
     fn from(value: Result<QueryResult, RecvError>) -> Self {
         match value {
@@ -180,20 +408,136 @@ impl From<Result<QueryResult, RecvError>> for QueryResult {
     }
 }
 
+/// Progress of a query sent with
+/// [`query_streamed`][crate::communicator::Communicator::query_streamed],
+/// updated in [`state_update`][crate::communicator::Communicator::state_update]
+/// as the chunks arrive.
+#[derive(Clone, Debug, Default)]
+pub struct QueryProgress(Arc<Mutex<ProgressState>>);
 
-#[derive(Clone)]
-pub struct FreshData<Key, Value>(HashMap<Key, Value>);
+#[derive(Debug, Default)]
+struct ProgressState {
+    fetched: usize,
+    total: Option<usize>,
+    error: Option<QueryError>,
+}
+
+impl QueryProgress {
+    /// Number of values recived so far.
+    pub fn fetched(&self) -> usize {
+        self.0.lock().unwrap().fetched
+    }
+    /// Wether every chunk was recived or the query failed.
+    pub fn is_done(&self) -> bool {
+        let state = self.0.lock().unwrap();
+        state.error.is_some() || state.total == Some(state.fetched)
+    }
+    pub fn error(&self) -> Option<QueryError> {
+        self.0.lock().unwrap().error.clone()
+    }
+    pub(crate) fn add_chunk(&self, len: usize) {
+        self.0.lock().unwrap().fetched += len;
+    }
+    /// Chunks might arrive out of order, so the query is only done once the
+    /// values of every chunk were counted.
+    pub(crate) fn end(&self, total: usize) {
+        self.0.lock().unwrap().total = Some(total);
+    }
+    pub(crate) fn fail(&self, error: QueryError) {
+        self.0.lock().unwrap().error = Some(error);
+    }
+}
+
+/// Values returned by a query. The values are behind an [`Arc`] so that
+/// cloning is cheap and storages that already keep their values in an `Arc`
+/// can hand them out without copying.
+#[derive(Clone, Debug)]
+pub struct FreshData<Key, Value> {
+    data: Arc<HashMap<Key, Value>>,
+    total: Option<usize>,
+    query_id: Option<u64>,
+    /// Set on the empty chunk marking the end of a streamed query, to the
+    /// number of values all chunks held.
+    stream_total: Option<usize>,
+}
+
+impl<Key, Value> PartialEq for FreshData<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key> + PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data && self.total == other.total
+    }
+}
+
+impl<Key, Value> FreshData<Key, Value> {
+    /// Sets the number of values the storage holds overall, for when only a
+    /// page of them is returned.
+    #[must_use]
+    pub fn with_total(mut self, total: usize) -> Self {
+        self.total = Some(total);
+        self
+    }
+    pub fn total(&self) -> Option<usize> {
+        self.total
+    }
+    #[must_use]
+    pub(crate) fn with_query_id(mut self, query_id: Option<u64>) -> Self {
+        self.query_id = query_id;
+        self
+    }
+    /// Id of the query these values are the result of.
+    pub(crate) fn query_id(&self) -> Option<u64> {
+        self.query_id
+    }
+    /// Marks the end of a streamed query whose chunks held `total` values.
+    pub(crate) fn stream_end(query_id: Option<u64>, total: usize) -> Self
+    where
+        Key: KeyBounds,
+        Value: ValueBounds<Key>,
+    {
+        let mut end = Self::from(HashMap::new()).with_query_id(query_id);
+        end.stream_total = Some(total);
+        end
+    }
+    pub(crate) fn stream_total(&self) -> Option<usize> {
+        self.stream_total
+    }
+    /// Adds the values of `other`, on key collision the values of `other`
+    /// win. The total of `self` is kept.
+    pub fn merge(&mut self, other: FreshData<Key, Value>)
+    where
+        Key: KeyBounds,
+        Value: ValueBounds<Key>,
+    {
+        if self.data.is_empty() {
+            self.data = other.data;
+            return;
+        }
+        Arc::make_mut(&mut self.data).extend(HashMap::from(other));
+    }
+    /// Takes the values without copying them.
+    pub fn into_shared(self) -> Arc<HashMap<Key, Value>> {
+        self.data
+    }
+}
 
 impl<Key, Value> Deref for FreshData<Key, Value> {
     type Target = HashMap<Key, Value>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.data
     }
 }
 
-impl<Key, Value> DerefMut for FreshData<Key, Value> {
+/// Copies the values if they are still shared.
+impl<Key, Value> DerefMut for FreshData<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        Arc::make_mut(&mut self.data)
     }
 }
 
@@ -205,7 +549,7 @@ where
     fn from(value: Value) -> Self {
         let mut map = HashMap::new();
         map.insert(value.key().clone(), value);
-        FreshData(map)
+        map.into()
     }
 }
 
@@ -215,12 +559,11 @@ where
     Value: ValueBounds<Key>,
 {
     fn from(value: Vec<Value>) -> Self {
-        FreshData(
-            value
-                .into_iter()
-                .map(|item| (item.key().clone(), item))
-                .collect(),
-        )
+        value
+            .into_iter()
+            .map(|item| (item.key().clone(), item))
+            .collect::<HashMap<_, _>>()
+            .into()
     }
 }
 
@@ -230,7 +573,22 @@ where
     Value: ValueBounds<Key>,
 {
     fn from(value: HashMap<Key, Value>) -> Self {
-        Self(value)
+        Arc::new(value).into()
+    }
+}
+
+impl<Key, Value> From<Arc<HashMap<Key, Value>>> for FreshData<Key, Value>
+where
+    Key: KeyBounds,
+    Value: ValueBounds<Key>,
+{
+    fn from(value: Arc<HashMap<Key, Value>>) -> Self {
+        Self {
+            data: value,
+            total: None,
+            query_id: None,
+            stream_total: None,
+        }
     }
 }
 
@@ -241,6 +599,6 @@ where
     Value: ValueBounds<Key>,
 {
     fn from(value: FreshData<Key, Value>) -> Self {
-        value.0
+        Arc::try_unwrap(value.data).unwrap_or_else(|shared| shared.as_ref().clone())
     }
 }
@@ -2,23 +2,65 @@ mod action;
 mod communicators;
 mod lib_impls;
 mod sequential;
+#[cfg(feature = "serde")]
+mod json;
+#[cfg(feature = "sqlite")]
+mod sql;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use futures::StreamExt;
 use itertools::Itertools;
-use lib_impls::TestStruct;
+use lib_impls::{
+    count_calls, Calls, CascadingStorage, ChunkFeed, ExternalFeed, ExternalStorage, FlakyStorage,
+    MigratingStorage, Migrations, RecordingStorage, RemainingFailures, SharedStorage,
+    SlowStorage, StreamingStorage, TestStruct, UnmigratableStorage, VersionedStorage,
+};
 use sequential::SequentialBuilder;
+use tokio::time::{sleep, Instant};
 
 use crate::{
     assert_action,
-    communicator::Communicator,
-    container::DataContainer,
-    query::QueryType,
+    communicator::{data::Move, Communicator},
+    container::{
+        conflict::ConflictStrategy,
+        errors::ContainerError,
+        retry::RetryPolicy,
+        storage::{
+            caching::CachingStorage,
+            in_memory::InMemoryStorage,
+            sharded::{ShardFn, ShardedStorage},
+            Storage, StorageCapabilities,
+        },
+        DataContainer, Signal, StateUpdateReport,
+    },
+    change::{ActionBatch, ChangeError, ChangeResult, ChangeType, DataChange},
+    query::{FreshData, Priority, QueryError, QueryResponse, QueryResult, QueryType, SortKey},
     query_action, ready_action,
+    utils::DrainIf,
 };
 
 type Comm = Communicator<usize, TestStruct>;
 type Cont = DataContainer<usize, TestStruct, HashMap<usize, TestStruct>>;
+type RecordingCont = DataContainer<usize, TestStruct, RecordingStorage>;
+type VersionedCont = DataContainer<usize, TestStruct, VersionedStorage>;
+type SharedCont = DataContainer<usize, TestStruct, SharedStorage>;
+type ExternalCont = DataContainer<usize, TestStruct, ExternalStorage>;
+type FlakyCont = DataContainer<usize, TestStruct, FlakyStorage>;
+type StreamingCont = DataContainer<usize, TestStruct, StreamingStorage>;
+type MigratingCont = DataContainer<usize, TestStruct, MigratingStorage>;
+type CascadingCont = DataContainer<usize, TestStruct, CascadingStorage>;
+type SlowCont = DataContainer<usize, TestStruct, SlowStorage>;
+type UnmigratableCont = DataContainer<usize, TestStruct, UnmigratableStorage>;
+type CachingCont = DataContainer<
+    usize,
+    TestStruct,
+    CachingStorage<usize, TestStruct, HashMap<usize, TestStruct>, RecordingStorage>,
+>;
 
 fn sequential(len: usize) -> SequentialBuilder {
     SequentialBuilder::new(len)
@@ -34,6 +76,32 @@ fn n_objects(number: usize, str: &str) -> Vec<TestStruct> {
         .collect_vec()
 }
 
+/// Spawns the future and keeps updating the container until it resolved.
+async fn drive<Writer, T>(
+    container: &mut DataContainer<usize, TestStruct, Writer>,
+    future: impl std::future::Future<Output = T> + Send + 'static,
+) -> T
+where
+    Writer: Storage<usize, TestStruct>,
+    T: Send + 'static,
+{
+    let handle = tokio::spawn(future);
+    while !handle.is_finished() {
+        container.state_update();
+        sleep(Duration::from_millis(5)).await;
+    }
+    handle.await.unwrap()
+}
+
+/// Keeps updating the container for a little while so that any outstanding
+/// sends can reach their communicators.
+async fn settle<Writer>(container: &mut DataContainer<usize, TestStruct, Writer>)
+where
+    Writer: Storage<usize, TestStruct>,
+{
+    drive(container, sleep(Duration::from_millis(20))).await;
+}
+
 #[tokio::test]
 async fn data_should_be_shared_to_everyone() {
     let [first_val_1, first_val_2] = multiply(TestStruct::new(1, "Hello One"));
@@ -230,3 +298,2240 @@ async fn pagination_sould_return_correct_page_and_page_size() {
 
     sequential(1).actions(actions).run().await;
 }
+
+#[tokio::test]
+async fn not_query_should_return_values_outside_of_inner_query() {
+    let [values] = multiply(n_objects(5, "test"));
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        query_action!(2, QueryType::Not(Box::new(QueryType::GetByIds(vec![1, 3])))),
+    ];
+
+    let final_state = sequential(2).actions(actions).run().await;
+
+    let mut keys = final_state.get(2).data.keys_cloned();
+    keys.sort();
+    assert_eq!(keys, vec![0, 2, 4]);
+}
+
+#[tokio::test]
+async fn move_should_place_value_between_neighbours() {
+    let [values] = multiply(n_objects(3, "test"));
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm.sort(|a, b| a.order.cmp(&b.order));
+            comm
+        }),
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.reorder(2, Some(1), Some(0)).await;
+            comm
+        }),
+    ];
+
+    let final_state = sequential(1).actions(actions).run().await;
+
+    let keys = final_state
+        .get(1)
+        .data
+        .sorted_iter()
+        .map(|value| value.key)
+        .collect_vec();
+    assert_eq!(keys, vec![0, 2, 1]);
+}
+
+#[tokio::test]
+async fn fetch_should_return_the_queried_values() {
+    let [values] = multiply(n_objects(3, "test"));
+    let fetched = Arc::new(Mutex::new(vec![]));
+    let fetched_in_action = fetched.clone();
+
+    let actions = vec![
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        ready_action!(2, |comm: Comm| async move {
+            let values = comm.fetch(QueryType::All).await.unwrap();
+            *fetched_in_action.lock().unwrap() = values;
+            comm
+        }),
+    ];
+
+    sequential(2).actions(actions).run().await;
+
+    let mut fetched = fetched.lock().unwrap().clone();
+    fetched.sort_by_key(|value| value.key);
+    assert_eq!(fetched, n_objects(3, "test"));
+}
+
+#[tokio::test]
+async fn sorted_position_should_follow_custom_sort() {
+    let values = vec![
+        TestStruct::new(1, "C"),
+        TestStruct::new(2, "A"),
+        TestStruct::new(3, "B"),
+    ];
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm.sort(|a, b| a.val.cmp(&b.val));
+            comm
+        }),
+        assert_action!(|data| {
+            let comm = data.get(1);
+            assert_eq!(comm.sorted_position(&2), Some(0));
+            assert_eq!(comm.sorted_position(&3), Some(1));
+            assert_eq!(comm.sorted_position(&1), Some(2));
+            assert_eq!(comm.sorted_position(&4), None);
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn budgeted_state_update_should_only_process_budgeted_actions() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+
+    let handles = n_objects(5, "test")
+        .into_iter()
+        .map(|value| tokio::spawn(comm.insert(value)))
+        .collect_vec();
+    sleep(Duration::from_millis(10)).await;
+
+    for expected_finished in [0, 2, 4, 5] {
+        container.state_update_budgeted(2);
+        sleep(Duration::from_millis(10)).await;
+        let finished = handles.iter().filter(|handle| handle.is_finished()).count();
+        assert_eq!(finished, expected_finished);
+    }
+}
+
+#[tokio::test]
+async fn predicate_query_should_fall_back_to_filtering_all_values() {
+    let [values] = multiply(n_objects(5, "test"));
+
+    let actions = vec![
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        query_action!(2, QueryType::predicate(|val: &TestStruct| val.key < 3)),
+    ];
+
+    let final_state = sequential(2).actions(actions).run().await;
+
+    let mut keys = final_state.get(2).data.keys_cloned();
+    keys.sort();
+    assert_eq!(keys, vec![0, 1, 2]);
+}
+
+#[tokio::test]
+async fn query_if_empty_should_not_reach_storage_when_data_exists() {
+    let calls = Calls::default();
+
+    let actions = vec![
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.query_if_empty(QueryType::All).await;
+            comm
+        }),
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert(TestStruct::new(1, "test")).await;
+            comm
+        }),
+        assert_action!(|data| { assert!(!data.get(1).is_empty()) }),
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.query_if_empty(QueryType::All).await;
+            comm
+        }),
+    ];
+
+    SequentialBuilder::<RecordingStorage>::new(1)
+        .actions(actions)
+        .run_with(calls.clone())
+        .await;
+    assert_eq!(count_calls(&calls, "get_all"), 1);
+}
+
+#[tokio::test]
+async fn communicator_interest_should_grow_after_query() {
+    let [values] = multiply(n_objects(3, "test"));
+
+    let actions = vec![
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        assert_action!(|data| {
+            let uuid = data.get(2).uuid();
+            let (keys, last_query) = data.container.communicator_interest(&uuid).unwrap();
+            assert!(keys.is_empty());
+            assert_eq!(last_query, None);
+        }),
+        query_action!(2, QueryType::All),
+        assert_action!(|data| {
+            let uuid = data.get(2).uuid();
+            let (keys, last_query) = data.container.communicator_interest(&uuid).unwrap();
+            assert_eq!(keys, (0..3).collect());
+            assert_eq!(last_query, Some(String::from("All")));
+        }),
+    ];
+
+    sequential(2).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn change_hooks_should_run_around_the_write() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    let comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "test"))).await;
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec!["before_change", "insert", "after_change"]
+    );
+}
+
+#[tokio::test]
+async fn data_sorted_page_should_match_data_page() {
+    let values = (0..5).map(|key| TestStruct::new(key, "test")).collect::<Vec<_>>();
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm.sort(|a, b| b.key.cmp(&a.key));
+            comm
+        }),
+        assert_action!(|data| {
+            let comm = data.get(1);
+            assert_eq!(comm.data_sorted_page(0, 2), comm.data.page(0, 2));
+            assert_eq!(comm.data_sorted_page(2, 2), comm.data.page(2, 2));
+            assert_eq!(
+                comm.data_sorted_page(1, 2)
+                    .unwrap()
+                    .iter()
+                    .map(|value| value.key)
+                    .collect::<Vec<_>>(),
+                vec![2, 1]
+            );
+            assert_eq!(comm.data_sorted_page(3, 2), None);
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn merge_strategy_should_combine_conflicting_inserts() {
+    let mut container = Cont::init(()).await;
+    container.set_conflict_strategy(ConflictStrategy::Merge(Box::new(|first, second| {
+        TestStruct::new(first.key, &format!("{}{}", first.val, second.val))
+    })));
+    let [first, second, mut reader] = container.communicators();
+
+    let first_insert = tokio::spawn(first.insert(TestStruct::new(1, "A")));
+    sleep(Duration::from_millis(5)).await;
+    let second_insert = tokio::spawn(second.insert(TestStruct::new(1, "B")));
+    sleep(Duration::from_millis(5)).await;
+    settle(&mut container).await;
+    assert!(first_insert.is_finished() && second_insert.is_finished());
+
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    settle(&mut container).await;
+    reader.state_update();
+    assert_eq!(reader.data.cloned(), vec![TestStruct::new(1, "AB")]);
+}
+
+#[tokio::test]
+async fn total_in_storage_should_survive_a_page_fetch() {
+    let [values] = multiply(n_objects(10, "test"));
+
+    let actions = vec![
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        assert_action!(|data| {
+            assert_eq!(data.get(2).total_in_storage(), None);
+        }),
+        query_action!(2, QueryType::GetByIds(vec![0, 1, 2])),
+        assert_action!(|data| {
+            let comm = data.get(2);
+            assert_eq!(comm.data.len(), 3);
+            assert_eq!(comm.total_in_storage(), Some(10));
+        }),
+        query_action!(2, QueryType::All),
+        assert_action!(|data| {
+            assert_eq!(data.get(2).total_in_storage(), Some(10));
+        }),
+    ];
+
+    sequential(2).actions(actions).run().await;
+}
+
+#[test]
+fn data_changes_and_fresh_data_should_compare_by_value() {
+    let change = DataChange::<usize, _>::Insert(n_objects(2, "test"));
+    assert_eq!(change, DataChange::Insert(n_objects(2, "test")));
+    assert_ne!(change, DataChange::Update(n_objects(2, "test")));
+
+    let fresh_data = FreshData::<usize, _>::from(n_objects(2, "test"));
+    assert_eq!(fresh_data, FreshData::from(n_objects(2, "test")));
+    assert_ne!(fresh_data, FreshData::from(n_objects(2, "test")).with_total(2));
+}
+
+#[tokio::test]
+async fn changed_since_should_only_return_newer_values() {
+    let mut container = VersionedCont::init(()).await;
+    let comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, comm.update(TestStruct::new(1, "changed"))).await;
+
+    let changed = drive(&mut container, comm.fetch(QueryType::changed_since(3)))
+        .await
+        .unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].key, 1);
+    assert_eq!(changed[0].val, "changed");
+    assert_eq!(changed[0].version, 4);
+}
+
+#[tokio::test]
+async fn identical_sort_keyed_should_not_resort() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+
+    comm.sort_keyed("by_val", |a, b| a.val.cmp(&b.val));
+    let resorts = comm.data.resort_count();
+
+    comm.sort_keyed("by_val", |a, b| a.val.cmp(&b.val));
+    assert_eq!(comm.data.resort_count(), resorts);
+
+    comm.sort_keyed("by_key", |a, b| a.key.cmp(&b.key));
+    assert_eq!(comm.data.resort_count(), resorts + 1);
+
+    comm.sort(|a, b| a.key.cmp(&b.key));
+    comm.sort_keyed("by_key", |a, b| a.key.cmp(&b.key));
+    assert_eq!(comm.data.resort_count(), resorts + 3);
+}
+
+#[tokio::test]
+async fn storage_errors_should_be_drained_from_the_container() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+
+    let _ = drive(&mut container, comm.query(QueryType::GetById(1))).await;
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "test"))).await;
+
+    let errors = container.drain_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], ContainerError::Query(QueryError::NotPresent)));
+    assert!(container.drain_errors().is_empty());
+
+    container.set_error_capacity(1);
+    let _ = drive(&mut container, comm.query(QueryType::GetById(2))).await;
+    let _ = drive(&mut container, comm.reorder(3, None, None)).await;
+    let errors = container.drain_errors();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], ContainerError::Change(_)));
+}
+
+#[tokio::test]
+async fn high_priority_query_should_be_processed_first() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    let comm = container.communicator();
+
+    let handles = (0..3)
+        .map(|key| tokio::spawn(comm.query_priority(QueryType::GetById(key), Priority::Normal)))
+        .collect_vec();
+    sleep(Duration::from_millis(5)).await;
+    let priority_handle = tokio::spawn(comm.query_priority(QueryType::All, Priority::High));
+    sleep(Duration::from_millis(5)).await;
+
+    container.state_update_budgeted(1);
+    assert_eq!(*calls.lock().unwrap(), vec!["get_all"]);
+
+    settle(&mut container).await;
+    assert!(priority_handle.is_finished());
+    assert!(handles.iter().all(|handle| handle.is_finished()));
+    assert_eq!(count_calls(&calls, "get_by_id"), 3);
+}
+
+#[tokio::test]
+async fn await_empty_should_resolve_after_all_values_are_deleted() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut reader] = container.communicators();
+
+    let _ = drive(&mut container, writer.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    settle(&mut container).await;
+    reader.state_update();
+    assert_eq!(reader.data.len(), 3);
+
+    let waiting = tokio::spawn(async move {
+        reader.await_empty().await;
+        reader
+    });
+    let _ = drive(&mut container, writer.delete_many(vec![0, 1, 2])).await;
+    let reader = drive(&mut container, waiting).await.unwrap();
+    assert!(reader.is_empty());
+}
+
+#[tokio::test]
+async fn map_sorted_should_map_in_sorted_order() {
+    let values = vec![
+        TestStruct::new(1, "C"),
+        TestStruct::new(2, "A"),
+        TestStruct::new(3, "B"),
+    ];
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm.sort(|a, b| a.val.cmp(&b.val));
+            comm
+        }),
+        assert_action!(|data| {
+            let vals = data.get(1).map_sorted(|value| value.val.clone());
+            assert_eq!(vals, vec!["A", "B", "C"]);
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn state_update_should_resort_once_for_many_changes() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut reader] = container.communicators();
+
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    settle(&mut container).await;
+    reader.state_update();
+    let resorts = reader.data.resort_count();
+
+    for value in n_objects(3, "test") {
+        let _ = drive(&mut container, writer.insert(value)).await;
+    }
+    settle(&mut container).await;
+    reader.state_update();
+
+    assert_eq!(reader.data.len(), 3);
+    assert_eq!(reader.data.resort_count(), resorts + 1);
+
+    reader.state_update();
+    assert_eq!(reader.data.resort_count(), resorts + 1);
+}
+
+#[tokio::test]
+async fn query_above_max_result_size_should_fail() {
+    let mut container = Cont::init(()).await;
+    container.set_max_query_result_size(Some(2));
+    let mut comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+
+    let result = drive(&mut container, comm.query(QueryType::All)).await.unwrap();
+    assert!(matches!(
+        result,
+        QueryResult::Error(QueryError::ResultTooLarge { size: 3, max: 2 })
+    ));
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(comm.is_empty());
+
+    let result = drive(&mut container, comm.query(QueryType::GetByIds(vec![0, 1])))
+        .await
+        .unwrap();
+    assert!(!matches!(result, QueryResult::Error(_)));
+}
+
+#[tokio::test]
+async fn snapshot_should_not_change_with_later_updates() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut reader] = container.communicators();
+
+    let _ = drive(&mut container, writer.insert_many(n_objects(2, "test"))).await;
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    settle(&mut container).await;
+    reader.state_update();
+
+    let snapshot = reader.snapshot();
+
+    let _ = drive(&mut container, writer.insert(TestStruct::new(2, "test"))).await;
+    let _ = drive(&mut container, writer.update(TestStruct::new(0, "changed"))).await;
+    settle(&mut container).await;
+    reader.state_update();
+    reader.sort(|a, b| b.key.cmp(&a.key));
+
+    assert_eq!(reader.data.len(), 3);
+    assert_eq!(snapshot.len(), 2);
+    assert_eq!(
+        snapshot.sorted(),
+        vec![&TestStruct::new(0, "test"), &TestStruct::new(1, "test")]
+    );
+    assert_eq!(snapshot.page(1, 1), Some(vec![&TestStruct::new(1, "test")]));
+}
+
+#[tokio::test]
+async fn comparator_equal_values_should_keep_insertion_order() {
+    let values = [3, 1, 4, 2]
+        .into_iter()
+        .map(|key| TestStruct::new(key, "same"))
+        .collect_vec();
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            comm.sort(|a, b| a.val.cmp(&b.val));
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        assert_action!(|data| {
+            let keys = data.get(1).map_sorted(|value| value.key);
+            assert_eq!(keys, vec![3, 1, 4, 2]);
+        }),
+        ready_action!(1, |mut comm: Comm| async move {
+            comm.sort(|a, b| a.val.cmp(&b.val));
+            comm
+        }),
+        assert_action!(|data| {
+            let keys = data.get(1).map_sorted(|value| value.key);
+            assert_eq!(keys, vec![3, 1, 4, 2]);
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn hashmap_storage_should_report_no_advanced_capabilities() {
+    let container = Cont::init(()).await;
+    let capabilities = container.storage_capabilities();
+
+    assert_eq!(capabilities, StorageCapabilities::default());
+    assert!(!capabilities.transactions);
+    assert!(!capabilities.range);
+    assert!(!capabilities.streaming);
+    assert!(!capabilities.external_changes);
+}
+
+#[tokio::test]
+async fn on_delete_should_only_fire_for_deletes() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut reader] = container.communicators();
+
+    let deleted = Arc::new(Mutex::new(Vec::new()));
+    let captured = deleted.clone();
+    reader.on_delete(move |keys| captured.lock().unwrap().extend_from_slice(keys));
+
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    let _ = drive(&mut container, writer.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, writer.update(TestStruct::new(0, "changed"))).await;
+    settle(&mut container).await;
+    reader.state_update();
+    assert!(deleted.lock().unwrap().is_empty());
+
+    let _ = drive(&mut container, writer.delete_many(vec![1, 2])).await;
+    settle(&mut container).await;
+    reader.state_update();
+    assert_eq!(*deleted.lock().unwrap(), vec![1, 2]);
+}
+
+#[tokio::test]
+async fn storage_should_be_accessible_through_the_container() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "test"))).await;
+    assert_eq!(container.storage().get(&1), Some(&TestStruct::new(1, "test")));
+
+    container.storage_mut().insert(2, TestStruct::new(2, "direct"));
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.len(), 2);
+}
+
+#[tokio::test]
+async fn queries_should_not_starve_behind_a_flood_of_changes() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    let comm = container.communicator();
+
+    let changes = n_objects(10, "test")
+        .into_iter()
+        .map(|value| tokio::spawn(comm.insert(value)))
+        .collect_vec();
+    sleep(Duration::from_millis(5)).await;
+    let query = tokio::spawn(comm.query(QueryType::All));
+    sleep(Duration::from_millis(5)).await;
+
+    container.state_update_budgeted(2);
+    assert_eq!(count_calls(&calls, "insert"), 1);
+    assert_eq!(count_calls(&calls, "get_all"), 1);
+
+    settle(&mut container).await;
+    assert!(query.is_finished());
+    assert!(changes.iter().all(|change| change.is_finished()));
+}
+
+#[tokio::test]
+async fn data_btreemap_should_be_ordered_by_key() {
+    let values = [3, 0, 2, 1]
+        .into_iter()
+        .map(|key| TestStruct::new(key, &format!("{}", 9 - key)))
+        .collect_vec();
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            let _ = comm.insert_many(values).await;
+            comm.sort(|a, b| a.val.cmp(&b.val));
+            comm
+        }),
+        assert_action!(|data| {
+            let comm = data.get(1);
+            assert_eq!(comm.data_btreemap().into_keys().collect_vec(), vec![0, 1, 2, 3]);
+            assert_eq!(comm.map_sorted(|value| value.key), vec![3, 2, 1, 0]);
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[test]
+fn results_should_convert_into_std_results() {
+    assert!(ChangeResult::Success.into_result().is_ok());
+    assert!(matches!(
+        ChangeResult::Error(ChangeError::DefaultError).into_result(),
+        Err(ChangeError::DefaultError)
+    ));
+
+    assert!(QueryResult::Success.into_result().is_ok());
+    assert!(QueryResult::Page { total: Some(3) }.into_result().is_ok());
+    assert!(matches!(
+        QueryResult::Error(QueryError::NotPresent).into_result(),
+        Err(QueryError::NotPresent)
+    ));
+}
+
+#[tokio::test]
+async fn sort_views_should_stay_sorted_after_insert() {
+    let [values] = multiply(vec![
+        TestStruct::new(1, "B"),
+        TestStruct::new(2, "C"),
+        TestStruct::new(3, "A"),
+    ]);
+
+    let actions = vec![
+        query_action!(1, QueryType::All),
+        ready_action!(1, |mut comm: Comm| async move {
+            comm.add_sort_view("by_val", |a, b| a.val.cmp(&b.val));
+            comm.add_sort_view("by_key_desc", |a, b| b.key.cmp(&a.key));
+            let _ = comm.insert_many(values).await;
+            comm
+        }),
+        ready_action!(1, |comm: Comm| async move {
+            let _ = comm.insert(TestStruct::new(0, "D")).await;
+            comm
+        }),
+        assert_action!(|data| {
+            let comm = data.get(1);
+            let keys = |view| {
+                comm.sorted_view(view)
+                    .unwrap()
+                    .iter()
+                    .map(|value| value.key)
+                    .collect_vec()
+            };
+            assert_eq!(keys("by_val"), vec![3, 1, 2, 0]);
+            assert_eq!(keys("by_key_desc"), vec![3, 2, 1, 0]);
+            assert!(comm.sorted_view("missing").is_none());
+        }),
+    ];
+
+    sequential(1).actions(actions).run().await;
+}
+
+#[tokio::test]
+async fn sort_view_should_only_resort_when_read() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let compared = Arc::new(Mutex::new(0));
+    let counter = compared.clone();
+    comm.add_sort_view("by_val", move |a: &TestStruct, b: &TestStruct| {
+        *counter.lock().unwrap() += 1;
+        a.val.cmp(&b.val)
+    });
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(*compared.lock().unwrap(), 0);
+
+    assert_eq!(comm.sorted_view("by_val").unwrap().len(), 4);
+    let after_read = *compared.lock().unwrap();
+    assert!(after_read > 0);
+    assert_eq!(comm.sorted_view("by_val").unwrap().len(), 4);
+    assert_eq!(*compared.lock().unwrap(), after_read);
+}
+
+#[tokio::test]
+async fn large_get_all_should_share_values_with_storage() {
+    let mut container = SharedCont::init(()).await;
+    let mut comm = container.communicator();
+
+    let values = n_objects(100_000, "test")
+        .into_iter()
+        .map(|value| (value.key, value))
+        .collect::<HashMap<_, _>>();
+    container.storage_mut().values = Arc::new(values);
+
+    let result = drive(&mut container, comm.query(QueryType::All)).await;
+    assert!(matches!(result, Ok(QueryResult::Success)));
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert_eq!(comm.data.len(), 100_000);
+    assert!(std::ptr::eq(comm.data.map(), container.storage().values.as_ref()));
+}
+
+#[tokio::test]
+async fn pending_changes_should_fall_once_answered() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+    assert_eq!(comm.pending_changes(), 0);
+
+    let insert = comm.insert(TestStruct::new(1, "test"));
+    let insert_many = comm.insert_many_action()(n_objects(2, "test"));
+    assert_eq!(comm.pending_changes(), 2);
+
+    let _ = drive(&mut container, insert).await;
+    assert_eq!(comm.pending_changes(), 1);
+
+    drop(insert_many);
+    assert_eq!(comm.pending_changes(), 0);
+}
+
+#[tokio::test]
+async fn change_channel_should_grow_on_burst() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut reader] = container.communicators();
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+
+    for value in n_objects(45, "burst") {
+        let _ = drive(&mut container, writer.insert(value)).await;
+    }
+    assert_eq!(container.change_capacity(&reader.uuid()), Some(40));
+
+    for _ in 0..20 {
+        reader.state_update();
+        settle(&mut container).await;
+    }
+    assert_eq!(reader.data.len(), 45);
+    assert!(reader.data.iter().all(|value| value.val == "burst"));
+}
+
+#[tokio::test]
+async fn full_change_channel_should_hold_back_changes_at_max() {
+    let mut container = Cont::init(()).await;
+    container.set_max_change_capacity(20);
+    let [writer, mut reader] = container.communicators();
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+
+    for value in n_objects(30, "burst") {
+        let _ = drive(&mut container, writer.insert(value)).await;
+    }
+    let _ = drive(&mut container, writer.delete(0)).await;
+    assert_eq!(container.change_capacity(&reader.uuid()), Some(20));
+
+    for _ in 0..5 {
+        reader.state_update();
+        settle(&mut container).await;
+    }
+    assert_eq!(reader.data.len(), 29);
+    assert!(!reader.data.map().contains_key(&0));
+}
+
+#[tokio::test]
+async fn filtered_communicator_should_be_subset_of_all() {
+    let mut container = Cont::init(()).await;
+    let [mut all, mut even, mut empty] = container.communicators();
+    let _ = drive(&mut container, all.insert_many(n_objects(10, "test"))).await;
+
+    let _ = drive(&mut container, all.query(QueryType::All)).await;
+    let even_query = QueryType::predicate(|value: &TestStruct| value.key.is_multiple_of(2));
+    let _ = drive(&mut container, even.query(even_query)).await;
+    settle(&mut container).await;
+    all.state_update();
+    even.state_update();
+    empty.state_update();
+
+    assert_eq!(even.key_set().len(), 5);
+    assert!(even.is_subset_of(&all));
+    assert!(!all.is_subset_of(&even));
+    assert!(even.shares_keys_with(&all));
+    assert!(empty.is_subset_of(&all));
+    assert!(!empty.shares_keys_with(&all));
+}
+
+#[tokio::test]
+async fn in_memory_storage_should_work_end_to_end() {
+    let mut container =
+        DataContainer::<usize, TestStruct, InMemoryStorage<usize, TestStruct>>::init(()).await;
+    let [writer, mut reader] = container.communicators();
+
+    let _ = drive(&mut container, writer.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    reader.state_update();
+    let update = drive(&mut container, writer.update(TestStruct::new(1, "changed"))).await;
+    let _ = drive(&mut container, writer.delete(2)).await;
+    settle(&mut container).await;
+    reader.state_update();
+
+    assert!(matches!(update, Ok(ChangeResult::Success)));
+    assert_eq!(reader.data.len(), 2);
+    assert_eq!(reader.data.map()[&1].val, "changed");
+    assert_eq!(container.storage().values().len(), 2);
+
+    let missing = drive(&mut container, writer.update(TestStruct::new(5, "missing"))).await;
+    assert!(matches!(missing, Ok(ChangeResult::Error(ChangeError::DatabaseError(_)))));
+    let missing = drive(&mut container, reader.query(QueryType::GetById(5))).await;
+    assert!(matches!(missing, Ok(QueryResult::Error(QueryError::NotPresent))));
+}
+
+#[tokio::test]
+async fn count_local_should_count_matching_values() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(10, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert_eq!(comm.count_local(|value| value.key < 3), 3);
+    assert_eq!(comm.count_local(|value| value.val == "test"), 10);
+    assert_eq!(comm.count_local(|value| value.val == "other"), 0);
+}
+
+#[tokio::test]
+async fn communicators_should_resync_after_external_stream_dropped() {
+    let feed = ExternalFeed::default();
+    let mut container = ExternalCont::init(feed.clone()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let external = TestStruct::new(5, "external");
+    container.storage_mut().values_mut().insert(5, external.clone());
+    let sender = feed.lock().unwrap().clone().unwrap();
+    sender.try_send(DataChange::Insert(vec![external])).unwrap();
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(comm.data.map().contains_key(&5));
+
+    drop(sender);
+    let dropped = feed.lock().unwrap().take().unwrap();
+    drop(dropped);
+    container.storage_mut().values_mut().insert(6, TestStruct::new(6, "missed"));
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert!(feed.lock().unwrap().is_some());
+    assert_eq!(comm.data.len(), 4);
+    assert_eq!(comm.data.map()[&6].val, "missed");
+}
+
+#[tokio::test]
+async fn take_changed_should_return_changed_keys_and_mark_viewed() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+    comm.set_viewed();
+    assert!(comm.changed_keys().is_empty());
+
+    let _ = drive(&mut container, comm.update(TestStruct::new(1, "changed"))).await;
+    let _ = drive(&mut container, comm.delete(3)).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert!(comm.has_changed());
+    assert_eq!(comm.take_changed(), HashSet::from([1, 3]));
+    assert!(!comm.has_changed());
+    assert!(comm.take_changed().is_empty());
+}
+
+#[tokio::test]
+async fn intersect_should_narrow_cached_keys() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut comm] = container.communicators();
+    let fruits = ["apple", "banana", "cherry", "mango", "orange", "pear"];
+    let values = fruits
+        .iter()
+        .enumerate()
+        .map(|(key, fruit)| TestStruct::new(key, fruit))
+        .collect_vec();
+    let _ = drive(&mut container, writer.insert_many(values)).await;
+
+    let _ = drive(&mut container, comm.query(QueryType::GetByIds(vec![0, 1, 2, 3]))).await;
+    comm.state_update();
+    let query = QueryType::intersect(comm.data.keys_cloned(), |value: &TestStruct| {
+        value.val.contains("an")
+    });
+    let intersecting = drive(&mut container, comm.fetch(query)).await.unwrap();
+
+    let keys = intersecting.iter().map(|value| value.key).sorted().collect_vec();
+    assert_eq!(keys, vec![1, 3]);
+}
+
+#[tokio::test]
+async fn broadcast_signal_should_reach_every_communicator() {
+    let mut container = Cont::init(()).await;
+    let [mut first, mut second] = container.communicators();
+
+    container.broadcast_signal(Signal::Reloaded);
+    container.broadcast_signal(Signal::Cleared);
+    first.state_update();
+    second.state_update();
+
+    assert_eq!(first.take_signals(), vec![Signal::Reloaded, Signal::Cleared]);
+    assert_eq!(second.take_signals(), vec![Signal::Reloaded, Signal::Cleared]);
+    assert!(first.take_signals().is_empty());
+}
+
+#[tokio::test]
+async fn get_or_should_return_default_for_missing_key() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "present"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let placeholder = TestStruct::new(0, "placeholder");
+    assert_eq!(comm.get_or(&1, &placeholder).val, "present");
+    assert_eq!(comm.get_or(&2, &placeholder), &placeholder);
+    assert_eq!(comm.data.get(&2), None);
+}
+
+#[tokio::test]
+async fn on_data_available_should_fire_after_receiving_changes() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let fired = Arc::new(Mutex::new(0));
+    let counter = fired.clone();
+    comm.on_data_available(move || *counter.lock().unwrap() += 1);
+
+    comm.state_update();
+    assert_eq!(*fired.lock().unwrap(), 0);
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(*fired.lock().unwrap(), 1);
+
+    comm.state_update();
+    assert_eq!(*fired.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn try_state_update_should_report_applied_updates() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    assert!(!comm.try_state_update());
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert(TestStruct::new(1, "test"))).await;
+    settle(&mut container).await;
+    assert!(comm.try_state_update());
+    assert!(!comm.try_state_update());
+}
+
+#[tokio::test]
+async fn delete_should_report_affected_count() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+
+    let present = drive(&mut container, comm.delete(1)).await;
+    let absent = drive(&mut container, comm.delete(7)).await;
+    let many = drive(&mut container, comm.delete_many(vec![0, 1, 2, 7])).await;
+
+    assert!(matches!(present, Ok(ChangeResult::Affected(1))));
+    assert!(matches!(absent, Ok(ChangeResult::Affected(0))));
+    assert!(matches!(many, Ok(ChangeResult::Affected(2))));
+    assert!(absent.unwrap().into_result().is_ok());
+}
+
+#[tokio::test]
+async fn offline_changes_should_converge_after_flush() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+
+    comm.queue_offline_change(ChangeType::InsertMany(n_objects(3, "offline")));
+    comm.queue_offline_change(ChangeType::Update(TestStruct::new(1, "edited")));
+    comm.queue_offline_change(ChangeType::Delete(0));
+    assert_eq!(comm.offline_changes(), 3);
+    assert_eq!(comm.data.len(), 2);
+    assert_eq!(comm.data.map()[&1].val, "edited");
+    assert!(container.storage().is_empty());
+
+    let results = drive(&mut container, comm.flush_offline()).await.unwrap();
+    assert_eq!(results.len(), 3);
+    assert!(results.into_iter().all(|result| result.into_result().is_ok()));
+    assert_eq!(comm.offline_changes(), 0);
+
+    let storage = container.storage();
+    assert_eq!(storage.len(), 2);
+    assert_eq!(storage[&1].val, "edited");
+    assert_eq!(storage[&2].val, "offline");
+
+    // changes that couldn't be sent stay queued
+    comm.queue_offline_change(ChangeType::Delete(1));
+    comm.queue_offline_change(ChangeType::Delete(2));
+    drop(container);
+    let results = comm.flush_offline().await.unwrap();
+    assert!(matches!(
+        results[..],
+        [ChangeResult::Error(ChangeError::ChannelSendError(_))]
+    ));
+    assert_eq!(comm.offline_changes(), 2);
+}
+
+#[tokio::test]
+async fn stats_should_summarize_data() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let stats = comm.stats();
+    assert_eq!(stats.len, 5);
+    assert!(stats.capacity >= 5);
+    assert!(!stats.is_sorted_custom);
+
+    comm.sort(|a, b| b.key.cmp(&a.key));
+    assert!(comm.stats().is_sorted_custom);
+}
+
+#[tokio::test]
+async fn empty_get_by_ids_should_not_reach_storage() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    let comm = container.communicator();
+
+    let result = drive(&mut container, comm.query(QueryType::GetByIds(vec![]))).await;
+    let fetched = drive(&mut container, comm.fetch(QueryType::GetByIds(vec![]))).await;
+
+    assert!(matches!(result, Ok(QueryResult::Success)));
+    assert!(fetched.unwrap().is_empty());
+    assert!(calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn window_should_return_values_around_center() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(10, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let keys = |values: Vec<&TestStruct>| values.iter().map(|value| value.key).collect_vec();
+    assert_eq!(keys(comm.window(&5, 2)), vec![3, 4, 5, 6, 7]);
+    assert_eq!(keys(comm.window(&1, 3)), vec![0, 1, 2, 3, 4]);
+    assert_eq!(keys(comm.window(&9, 1)), vec![8, 9]);
+    assert!(comm.window(&42, 2).is_empty());
+}
+
+#[tokio::test]
+async fn only_latest_of_rapid_queries_should_deliver_data() {
+    let mut container = Cont::init(()).await;
+    container.set_suppress_stale_queries(true);
+    let [writer, mut comm] = container.communicators();
+    let _ = drive(&mut container, writer.insert_many(n_objects(5, "test"))).await;
+
+    let handles = (0..3)
+        .map(|key| {
+            let query = QueryType::predicate(move |value: &TestStruct| value.key == key);
+            tokio::spawn(comm.query(query))
+        })
+        .collect_vec();
+    sleep(Duration::from_millis(5)).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert!(handles.iter().all(|handle| handle.is_finished()));
+    assert_eq!(comm.data.keys_cloned(), vec![2]);
+}
+
+#[tokio::test]
+async fn change_stream_should_yield_and_apply_changes() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut comm] = container.communicators();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let _ = drive(&mut container, writer.insert(TestStruct::new(1, "first"))).await;
+    let _ = drive(&mut container, writer.insert(TestStruct::new(2, "second"))).await;
+    settle(&mut container).await;
+
+    let changes = comm.change_stream().take(2).collect::<Vec<_>>().await;
+    assert_eq!(
+        changes,
+        vec![
+            DataChange::Insert(vec![TestStruct::new(1, "first")]),
+            DataChange::Insert(vec![TestStruct::new(2, "second")]),
+        ]
+    );
+    assert_eq!(comm.data.len(), 2);
+}
+
+#[tokio::test]
+async fn change_stream_should_wake_on_change_and_end_with_container() {
+    let mut container = Cont::init(()).await;
+    let [writer, mut comm] = container.communicators();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let mut changes = Box::pin(comm.change_stream());
+    let insert = async {
+        let _ = drive(&mut container, writer.insert(TestStruct::new(1, "first"))).await;
+        settle(&mut container).await;
+    };
+    let (change, ()) = tokio::join!(changes.next(), insert);
+    assert_eq!(change, Some(DataChange::Insert(vec![TestStruct::new(1, "first")])));
+
+    drop(container);
+    let end = tokio::time::timeout(Duration::from_secs(1), changes.next()).await;
+    assert_eq!(end.ok(), Some(None));
+}
+
+#[tokio::test]
+async fn sharded_storage_should_route_by_key() {
+    type Shards = ShardedStorage<usize, TestStruct, HashMap<usize, TestStruct>>;
+    let by_parity: ShardFn<usize> = Arc::new(|key| key % 2);
+    let mut container = DataContainer::<usize, TestStruct, Shards>::init((vec![(), ()], by_parity))
+        .await;
+    let mut comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    let _ = drive(&mut container, comm.update(TestStruct::new(3, "changed"))).await;
+    let deleted = drive(&mut container, comm.delete_many(vec![0, 1])).await;
+    assert!(matches!(deleted, Ok(ChangeResult::Affected(2))));
+
+    let shards = container.storage().shards();
+    assert_eq!(shards[0].keys().sorted().collect_vec(), vec![&2, &4]);
+    assert_eq!(shards[1].keys().sorted().collect_vec(), vec![&3]);
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+    assert_eq!(comm.data.len(), 3);
+    assert_eq!(comm.data.map()[&3].val, "changed");
+
+    let ids = drive(&mut container, comm.fetch(QueryType::GetByIds(vec![2, 3]))).await;
+    assert_eq!(ids.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn sorted_diff_should_describe_moves_after_insert() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let values = [0, 1, 3, 4].map(|key| TestStruct::new(key, "test")).to_vec();
+    let _ = drive(&mut container, comm.insert_many(values)).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+    let previous = comm.map_sorted(|value| value.key);
+
+    let _ = drive(&mut container, comm.insert(TestStruct::new(2, "test"))).await;
+    let _ = drive(&mut container, comm.delete(0)).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert_eq!(
+        comm.sorted_diff(&previous),
+        vec![
+            Move::Removed { key: 0, from: 0 },
+            Move::Moved { key: 1, from: 1, to: 0 },
+            Move::Inserted { key: 2, to: 1 },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn init_with_retry_should_succeed_after_failed_attempts() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(1),
+        timeout: Duration::from_secs(1),
+    };
+
+    let failures: RemainingFailures = Arc::new(Mutex::new(2));
+    let container = FlakyCont::init_with_retry(failures.clone(), policy).await;
+    assert!(container.is_ok());
+    assert_eq!(*failures.lock().unwrap(), 0);
+
+    let mut container = container.unwrap();
+    let comm = container.communicator();
+    let inserted = drive(&mut container, comm.insert(TestStruct::new(0, "test"))).await;
+    assert!(inserted.is_ok());
+
+    let failures: RemainingFailures = Arc::new(Mutex::new(3));
+    let container = FlakyCont::init_with_retry(failures, policy).await;
+    assert!(container.is_err_and(|err| err.0 == "connection refused"));
+}
+
+#[tokio::test]
+async fn is_loading_should_be_true_until_data_is_recived() {
+    let mut container = Cont::init(()).await;
+    let [mut reader, writer] = container.communicators();
+    let _ = drive(&mut container, writer.insert_many(n_objects(3, "test"))).await;
+    assert!(!reader.is_loading());
+
+    let _ = drive(&mut container, reader.query(QueryType::All)).await;
+    assert!(reader.is_loading());
+    settle(&mut container).await;
+    reader.state_update();
+    assert!(!reader.is_loading());
+    assert_eq!(reader.data.len(), 3);
+
+    let result = drive(&mut container, reader.query(QueryType::GetById(10))).await;
+    assert!(matches!(result, Ok(QueryResult::Error(_))));
+    assert!(!reader.is_loading());
+}
+
+#[tokio::test]
+async fn concurrent_modify_should_not_lose_increments() {
+    let mut container = Cont::init(()).await;
+    let [mut first, second] = container.communicators();
+    let _ = drive(&mut container, first.insert(TestStruct::new(0, "counter"))).await;
+    let _ = drive(&mut container, first.query(QueryType::All)).await;
+    first.state_update();
+
+    let increments = (0..10)
+        .map(|n| {
+            let comm = if n % 2 == 0 { &first } else { &second };
+            comm.modify(0, |value: &mut TestStruct| value.version += 1)
+        })
+        .collect_vec();
+    let results = drive(&mut container, futures::future::join_all(increments)).await;
+    assert!(results.iter().all(|result| matches!(result, Ok(ChangeResult::Success))));
+    assert_eq!(container.storage()[&0].version, 10);
+
+    settle(&mut container).await;
+    first.state_update();
+    assert_eq!(first.data.map()[&0].version, 10);
+}
+
+#[test]
+fn drain_if_should_keep_the_original_order() {
+    let mut values = (0..6).collect_vec();
+    let drained = values.drain_if(|value| *value % 2 == 0);
+    assert_eq!(drained, vec![0, 2, 4]);
+    assert_eq!(values, vec![1, 3, 5]);
+}
+
+#[tokio::test]
+async fn tick_hook_should_observe_finished_changes() {
+    let mut container = Cont::init(()).await;
+    let reports = Arc::new(Mutex::new(Vec::<StateUpdateReport>::new()));
+    let hook_reports = reports.clone();
+    container.set_tick_hook(move |report| hook_reports.lock().unwrap().push(*report));
+    let comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert(TestStruct::new(0, "test"))).await;
+
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.iter().map(|report| report.new_actions).sum::<usize>(), 1);
+    assert_eq!(reports.iter().map(|report| report.changes).sum::<usize>(), 1);
+    assert!(reports.iter().all(|report| report.errors == 0));
+}
+
+#[tokio::test]
+async fn sort_by_key_default_should_restore_key_order() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    comm.sort(|a: &TestStruct, b: &TestStruct| b.key.cmp(&a.key));
+    assert_eq!(comm.map_sorted(|value| value.key), vec![3, 2, 1, 0]);
+    assert!(comm.stats().is_sorted_custom);
+
+    comm.sort_by_key_default();
+    assert_eq!(comm.map_sorted(|value| value.key), vec![0, 1, 2, 3]);
+    assert!(!comm.stats().is_sorted_custom);
+}
+
+#[test]
+fn fresh_data_merge_should_let_later_values_win() {
+    let mut fresh_data: FreshData<usize, TestStruct> = n_objects(2, "first").into();
+    let later = FreshData::from(vec![TestStruct::new(1, "second"), TestStruct::new(2, "second")]);
+    fresh_data.merge(later);
+
+    assert_eq!(fresh_data.len(), 3);
+    assert_eq!(fresh_data[&0].val, "first");
+    assert_eq!(fresh_data[&1].val, "second");
+    assert_eq!(fresh_data[&2].val, "second");
+}
+
+#[tokio::test]
+async fn insert_then_should_call_back_with_result() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let results = Arc::new(Mutex::new(vec![]));
+    let callback_results = results.clone();
+    comm.insert_then(TestStruct::new(0, "test"), move |result| {
+        callback_results.lock().unwrap().push(result)
+    });
+
+    for _ in 0..100 {
+        container.state_update();
+        comm.state_update();
+        if !results.lock().unwrap().is_empty() {
+            break;
+        }
+        sleep(Duration::from_millis(5)).await;
+    }
+    let results = results.lock().unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(matches!(results[0], ChangeResult::Success));
+    assert!(container.storage().contains_key(&0));
+}
+
+#[tokio::test]
+async fn data_filtered_should_accept_borrowing_predicate() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let values = vec![
+        TestStruct::new(0, "apple"),
+        TestStruct::new(1, "banana"),
+        TestStruct::new(2, "pineapple"),
+    ];
+    let _ = drive(&mut container, comm.insert_many(values)).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+
+    let search = String::from("apple");
+    let matches = comm.data_filtered(|value| value.val.contains(search.as_str()));
+    assert_eq!(matches.iter().map(|value| value.key).collect_vec(), vec![0, 2]);
+    assert_eq!(comm.count_local(|value| value.val.contains(search.as_str())), 2);
+}
+
+#[tokio::test]
+async fn prune_idle_communicators_should_forget_dropped_ones() {
+    let mut container = Cont::init(()).await;
+    let [kept, dropped] = container.communicators();
+    let dropped_uuid = dropped.uuid();
+    let _ = drive(&mut container, dropped.query(QueryType::All)).await;
+    drop(dropped);
+
+    assert!(container.communicator_interest(&dropped_uuid).is_some());
+    assert_eq!(container.prune_idle_communicators(), 1);
+    assert!(container.communicator_interest(&dropped_uuid).is_none());
+    assert!(container.communicator_interest(&kept.uuid()).is_some());
+    assert_eq!(container.prune_idle_communicators(), 0);
+
+    container.set_prune_idle_communicators(true);
+    let closed_later = container.communicator();
+    let closed_later_uuid = closed_later.uuid();
+    drop(closed_later);
+    container.state_update();
+    assert!(container.communicator_interest(&closed_later_uuid).is_none());
+
+    let inserted = drive(&mut container, kept.insert(TestStruct::new(0, "test"))).await;
+    assert!(inserted.is_ok());
+}
+
+#[tokio::test]
+async fn pruning_communicator_with_query_in_flight_should_not_panic() {
+    let mut container = Cont::init(()).await;
+    let [kept, running, waiting] = container.communicators();
+    let _ = drive(&mut container, kept.insert_many(n_objects(3, "test"))).await;
+
+    let running_query = tokio::spawn(running.query(QueryType::All));
+    sleep(Duration::from_millis(5)).await;
+    container.state_update();
+    let waiting_query = tokio::spawn(waiting.query(QueryType::All));
+    sleep(Duration::from_millis(5)).await;
+    drop(running);
+    drop(waiting);
+
+    assert_eq!(container.prune_idle_communicators(), 2);
+    container.state_update();
+    settle(&mut container).await;
+    assert!(running_query.await.is_ok());
+    assert!(waiting_query.await.is_ok());
+}
+
+#[tokio::test]
+async fn query_streamed_should_report_progress_per_chunk() {
+    let feed: ChunkFeed = Arc::new(Mutex::new(None));
+    let mut container = StreamingCont::init(feed.clone()).await;
+    let mut comm = container.communicator();
+
+    let progress = comm.query_streamed(QueryType::All);
+    while feed.lock().unwrap().is_none() {
+        container.state_update();
+        sleep(Duration::from_millis(1)).await;
+    }
+    assert!(comm.is_loading());
+
+    let chunks = [n_objects(2, "first"), vec![TestStruct::new(2, "second")]];
+    let mut fetched = vec![];
+    for chunk in chunks {
+        let sender = feed.lock().unwrap().clone().unwrap();
+        sender.send(QueryResponse::Ok(chunk.into())).await.unwrap();
+        settle(&mut container).await;
+        comm.state_update();
+        fetched.push(progress.fetched());
+        assert!(!progress.is_done());
+    }
+    assert_eq!(fetched, vec![2, 3]);
+    assert_eq!(comm.data.len(), 3);
+
+    feed.lock().unwrap().take();
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(progress.is_done());
+    assert!(progress.error().is_none());
+    assert!(!comm.is_loading());
+
+    // keys of earlier chunks still recive updates
+    let updated = TestStruct::new(0, "updated");
+    container.force_broadcast(DataChange::Update(vec![updated.clone()]));
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.get(&0), Some(&updated));
+
+    // storages that don't stream return everything as a single chunk
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+    let progress = comm.query_streamed(QueryType::All);
+    while !progress.is_done() {
+        container.state_update();
+        comm.state_update();
+        sleep(Duration::from_millis(1)).await;
+    }
+    assert_eq!(progress.fetched(), 3);
+    assert_eq!(comm.data.len(), 3);
+}
+
+#[test]
+fn query_streamed_should_not_need_a_runtime() {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let mut container = runtime.block_on(Cont::init(()));
+    let mut comm = container.communicator();
+
+    let progress = comm.query_streamed(QueryType::All);
+    runtime.block_on(async {
+        while !progress.is_done() {
+            container.state_update();
+            comm.state_update();
+            sleep(Duration::from_millis(1)).await;
+        }
+    });
+    assert!(progress.error().is_none());
+}
+
+#[test]
+fn storage_resolved_changes_should_not_convert_into_data_changes() {
+    let insert = ChangeType::Insert(TestStruct::new(0, "test"));
+    assert!(DataChange::<usize, TestStruct>::try_from(insert).is_ok());
+
+    let move_change = ChangeType::<usize, TestStruct>::Move {
+        key: 0,
+        before: None,
+        after: Some(1),
+    };
+    assert!(DataChange::try_from(move_change).is_err());
+}
+
+#[test]
+fn io_errors_should_convert_into_storage_errors() {
+    fn read_file() -> Result<(), ChangeError> {
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, "missing file"))?;
+        Ok(())
+    }
+    assert!(matches!(read_file(), Err(ChangeError::DatabaseError(msg)) if msg == "missing file"));
+
+    let boxed: Box<dyn std::error::Error + Send + Sync> = "connection lost".into();
+    let query_error = QueryError::from(boxed);
+    assert!(matches!(query_error, QueryError::Backend(msg) if msg == "connection lost"));
+    let io_error = std::io::Error::other("disk full");
+    assert!(matches!(QueryError::from(io_error), QueryError::Backend(msg) if msg == "disk full"));
+}
+
+#[tokio::test]
+async fn values_between_should_use_active_sort() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let values = ["d", "a", "e", "c", "b"]
+        .into_iter()
+        .enumerate()
+        .map(|(key, val)| TestStruct::new(key, val))
+        .collect_vec();
+    let _ = drive(&mut container, comm.insert_many(values)).await;
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| a.val.cmp(&b.val));
+
+    let (lo, hi) = (TestStruct::new(10, "b"), TestStruct::new(11, "d"));
+    let between = comm.values_between(&lo, &hi);
+    assert_eq!(between.iter().map(|value| value.val.as_str()).collect_vec(), ["b", "c", "d"]);
+
+    let (lo, hi) = (TestStruct::new(10, "bb"), TestStruct::new(11, "z"));
+    let between = comm.values_between(&lo, &hi);
+    assert_eq!(between.iter().map(|value| value.val.as_str()).collect_vec(), ["c", "d", "e"]);
+    assert!(comm.values_between(&hi, &lo).is_empty());
+}
+
+#[tokio::test]
+async fn bulk_insert_should_reach_every_all_communicator() {
+    const ROWS: usize = 10_000;
+    let mut container = Cont::init(()).await;
+    let mut comms: [Comm; 4] = container.communicators();
+    for comm in comms.iter_mut() {
+        let _ = drive(&mut container, comm.query(QueryType::All)).await;
+        comm.state_update();
+    }
+    let mut filtered = container.communicator();
+    let even = |value: &TestStruct| value.key.is_multiple_of(2);
+    let _ = drive(&mut container, filtered.query(QueryType::predicate(even))).await;
+
+    let inserted = drive(&mut container, comms[0].insert_many(n_objects(ROWS, "seed"))).await;
+    assert!(inserted.is_ok());
+    settle(&mut container).await;
+
+    for comm in comms.iter_mut() {
+        comm.state_update();
+        assert_eq!(comm.data.len(), ROWS);
+    }
+    filtered.state_update();
+    assert_eq!(filtered.data.len(), ROWS / 2);
+}
+
+#[tokio::test]
+async fn set_viewed_keys_should_only_acknowledge_given_keys() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    comm.state_update();
+    comm.set_viewed();
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(comm.has_changed());
+
+    comm.set_viewed_keys(&[0, 1]);
+    assert!(comm.has_changed());
+    assert_eq!(comm.changed_keys(), &HashSet::from([2]));
+
+    comm.set_viewed_keys(&[2]);
+    assert!(!comm.has_changed());
+    assert!(comm.changed_keys().is_empty());
+}
+
+#[tokio::test]
+async fn init_should_migrate_storage_once() {
+    let migrations: Migrations = Arc::new(Mutex::new(0));
+    let mut container = MigratingCont::init(migrations.clone()).await;
+    assert_eq!(*migrations.lock().unwrap(), 1);
+
+    let comm = container.communicator();
+    let _ = drive(&mut container, comm.insert(TestStruct::new(0, "test"))).await;
+    assert_eq!(*migrations.lock().unwrap(), 1);
+    assert!(container.drain_errors().is_empty());
+
+    let retried = MigratingCont::init_with_retry(migrations.clone(), RetryPolicy::default()).await;
+    assert!(retried.is_ok());
+    assert_eq!(*migrations.lock().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn failed_migration_should_refuse_every_action() {
+    let mut container = UnmigratableCont::init(()).await;
+    assert_eq!(container.drain_errors().len(), 1);
+    let comm = container.communicator();
+
+    let inserted = drive(&mut container, comm.insert(TestStruct::new(0, "test"))).await;
+    assert!(matches!(inserted, Ok(ChangeResult::Error(ChangeError::DatabaseError(_)))));
+    let queried = drive(&mut container, comm.query(QueryType::All)).await;
+    assert!(matches!(queried, Ok(QueryResult::Error(QueryError::Backend(_)))));
+    assert!(container.storage().values.is_empty());
+
+    let retried = UnmigratableCont::init_with_retry((), RetryPolicy::default()).await;
+    assert!(retried.is_err());
+}
+
+#[tokio::test]
+async fn sharded_storage_should_forward_reorder_and_rekey() {
+    type Shards = ShardedStorage<usize, TestStruct, HashMap<usize, TestStruct>>;
+    let by_parity: ShardFn<usize> = Arc::new(|key| key % 2);
+    let mut container = DataContainer::<usize, TestStruct, Shards>::init((vec![(), ()], by_parity))
+        .await;
+    let comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+
+    let reordered = drive(&mut container, comm.reorder(2, None, Some(0))).await;
+    assert!(reordered.unwrap().into_result().is_ok());
+    let rekeyed = drive(&mut container, comm.rekey(1, 5)).await;
+    assert!(rekeyed.unwrap().into_result().is_ok());
+    let across = drive(&mut container, comm.rekey(0, 7)).await;
+    assert!(matches!(across, Ok(ChangeResult::Error(ChangeError::Unsupported(_)))));
+
+    let shards = container.storage().shards();
+    assert_eq!(shards[0].keys().sorted().collect_vec(), vec![&0, &2]);
+    assert_eq!(shards[1].keys().sorted().collect_vec(), vec![&3, &5]);
+}
+
+#[tokio::test]
+async fn sharded_storage_should_migrate_every_shard() {
+    type Shards = ShardedStorage<usize, TestStruct, MigratingStorage>;
+    let migrations: Migrations = Arc::new(Mutex::new(0));
+    let by_parity: ShardFn<usize> = Arc::new(|key| key % 2);
+    let shard_args = vec![migrations.clone(), migrations.clone()];
+    let _container =
+        DataContainer::<usize, TestStruct, Shards>::init((shard_args, by_parity)).await;
+    assert_eq!(*migrations.lock().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn skip_equal_updates_should_not_flag_identical_values() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    comm.skip_equal_updates(true);
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert(TestStruct::new(0, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.set_viewed();
+
+    let _ = drive(&mut container, comm.update(TestStruct::new(0, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(!comm.has_changed());
+
+    let _ = drive(&mut container, comm.update(TestStruct::new(0, "changed"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(comm.has_changed());
+    assert_eq!(comm.data.get(&0).unwrap().val, "changed");
+}
+
+#[tokio::test]
+async fn debug_report_should_list_all_communicators() {
+    let mut container = Cont::init(()).await;
+    let [comm1, comm2] = container.communicators();
+    let _ = drive(&mut container, comm1.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, comm2.query(QueryType::All)).await;
+    settle(&mut container).await;
+
+    let report = container.debug_report();
+    assert_eq!(report.communicators.len(), 2);
+    assert_eq!(report.running_actions, 0);
+
+    let info_of = |uuid| {
+        report
+            .communicators
+            .iter()
+            .find(|info| info.uuid == uuid)
+            .unwrap()
+            .clone()
+    };
+    let info1 = info_of(comm1.uuid());
+    assert_eq!(info1.last_query, None);
+    let info2 = info_of(comm2.uuid());
+    assert_eq!(info2.keys, 3);
+    assert_eq!(info2.last_query, Some(String::from("All")));
+    assert_eq!(info2.held_back_changes, 0);
+}
+
+#[tokio::test]
+async fn communicator_should_work_after_into_and_from_parts() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+    let uuid = comm.uuid();
+
+    let (parts_uuid, sender, reciver) = comm.into_parts();
+    assert_eq!(parts_uuid, uuid);
+    let mut comm = Comm::from_parts(parts_uuid, sender, reciver);
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.uuid(), uuid);
+    assert_eq!(comm.data.len(), 2);
+}
+
+#[tokio::test]
+async fn cached_all_queries_should_hit_storage_once_until_change() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    container.set_cache_all_queries(true);
+    let [comm1, comm2] = container.communicators();
+
+    let _ = drive(&mut container, comm1.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm2.query(QueryType::All)).await;
+    assert_eq!(count_calls(&calls, "get_all"), 1);
+
+    let _ = drive(&mut container, comm1.insert(TestStruct::new(0, "test"))).await;
+    settle(&mut container).await;
+    let _ = drive(&mut container, comm2.query(QueryType::All)).await;
+    assert_eq!(count_calls(&calls, "get_all"), 2);
+}
+
+#[tokio::test]
+async fn prune_to_query_should_drop_non_matching_values() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(6, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.set_viewed();
+
+    let even = QueryType::Predicate(Arc::new(|value: &TestStruct| value.key.is_multiple_of(2)));
+    assert_eq!(comm.prune_to_query(&even), 3);
+    assert!(comm.has_changed());
+    assert_eq!(comm.data.keys_iter().copied().sorted().collect_vec(), vec![0, 2, 4]);
+
+    assert_eq!(comm.prune_to_query(&even), 0);
+}
+
+#[tokio::test]
+async fn action_batch_should_join_results_of_generated_actions() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+
+    let mut insert = comm.insert_action();
+    let batch = n_objects(3, "test").into_iter().map(&mut insert).collect::<ActionBatch>();
+    assert_eq!(batch.len(), 3);
+
+    let results = drive(&mut container, batch.join()).await;
+    assert_eq!(results.len(), 3);
+    assert!(results.into_iter().all(|result| result.into_result().is_ok()));
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.len(), 3);
+}
+
+#[tokio::test]
+async fn await_communicator_synced_should_deliver_everything() {
+    let mut container = Cont::init(()).await;
+    let [comm1, mut comm2] = container.communicators();
+    let _ = drive(&mut container, comm2.query(QueryType::All)).await;
+
+    let _ = drive(&mut container, comm1.insert_many(n_objects(3, "test"))).await;
+    container.await_communicator_synced(comm2.uuid()).await;
+    comm2.state_update();
+    assert_eq!(comm2.data.len(), 3);
+    assert_eq!(container.debug_report().pending_sends, 0);
+}
+
+#[tokio::test]
+async fn watch_map_should_see_inserted_values() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let mut watch = comm.watch_map();
+    assert!(watch.borrow_and_update().is_empty());
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert!(watch.has_changed().unwrap());
+    let map = watch.borrow_and_update().clone();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get(&1), Some(&TestStruct::new(1, "test")));
+}
+
+#[tokio::test]
+async fn insert_if_absent_many_should_only_add_new_keys() {
+    let mut container = Cont::init(()).await;
+    let [comm1, mut comm2] = container.communicators();
+    let _ = drive(&mut container, comm2.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm1.insert_many(n_objects(2, "existing"))).await;
+    settle(&mut container).await;
+    comm2.state_update();
+    comm2.set_viewed();
+
+    let result = drive(&mut container, comm1.insert_if_absent_many(n_objects(4, "seed")))
+        .await
+        .unwrap();
+    assert!(matches!(result, ChangeResult::Affected(2)));
+    settle(&mut container).await;
+    comm2.state_update();
+
+    assert_eq!(comm2.data.len(), 4);
+    assert_eq!(comm2.data.get(&1).unwrap().val, "existing");
+    assert_eq!(comm2.data.get(&3).unwrap().val, "seed");
+    assert_eq!(comm2.changed_keys(), &HashSet::from([2, 3]));
+}
+
+#[tokio::test]
+async fn nth_sorted_should_match_sorted_data() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(10, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| (a.key * 7 % 10).cmp(&(b.key * 7 % 10)));
+
+    let sorted = comm.data.sorted();
+    for (n, value) in sorted.iter().enumerate() {
+        assert_eq!(comm.nth_sorted(n), Some(*value));
+    }
+    assert_eq!(comm.nth_sorted(1).unwrap().key, 3);
+    assert_eq!(comm.nth_sorted(10), None);
+}
+
+#[tokio::test]
+async fn caching_storage_should_read_backend_only_on_miss() {
+    let calls = Calls::default();
+    let mut container = CachingCont::init(((), calls.clone())).await;
+    let comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    assert_eq!(count_calls(&calls, "insert_many"), 1);
+    container.storage().with_cache(|cache| cache.clear());
+
+    for _ in 0..2 {
+        let values = drive(&mut container, comm.fetch(QueryType::GetById(1))).await;
+        assert_eq!(values.unwrap(), vec![TestStruct::new(1, "test")]);
+    }
+    assert_eq!(count_calls(&calls, "get_by_id"), 1);
+    assert!(container.storage().with_cache(|cache| cache.contains_key(&1)));
+}
+
+#[tokio::test]
+async fn key_of_and_index_of_key_should_round_trip() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| b.key.cmp(&a.key));
+
+    for n in 0..5 {
+        let value = comm.nth_sorted(n).unwrap();
+        let key = comm.key_of(value);
+        assert_eq!(*key, 4 - n);
+        assert_eq!(comm.index_of_key(key), Some(n));
+    }
+    assert_eq!(comm.index_of_key(&5), None);
+}
+
+#[tokio::test]
+async fn dropped_communicator_should_be_skipped_when_broadcasting() {
+    let mut container = Cont::init(()).await;
+    let [mut kept, dropped] = container.communicators();
+    let _ = drive(&mut container, kept.query(QueryType::All)).await;
+    let _ = drive(&mut container, dropped.query(QueryType::All)).await;
+    let dropped_uuid = dropped.uuid();
+
+    let insert = dropped.insert(TestStruct::new(0, "test"));
+    drop(dropped);
+    let result = drive(&mut container, insert).await.unwrap();
+    assert!(result.into_result().is_ok());
+    settle(&mut container).await;
+
+    assert!(container.storage().contains_key(&0));
+    let (keys, _) = container.communicator_interest(&dropped_uuid).unwrap();
+    assert!(keys.is_empty());
+    kept.state_update();
+    assert_eq!(kept.data.len(), 1);
+}
+
+#[tokio::test]
+async fn group_count_should_count_values_per_group() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let values = ["apple", "avocado", "banana", "cherry", "cranberry", "coconut"]
+        .into_iter()
+        .enumerate()
+        .map(|(key, val)| TestStruct::new(key, val))
+        .collect_vec();
+    let _ = drive(&mut container, comm.insert_many(values)).await;
+
+    let query = QueryType::group_count(|value: &TestStruct| value.val[..1].to_string());
+    let result = drive(&mut container, comm.query(query)).await.unwrap();
+    let QueryResult::Groups(groups) = result else {
+        panic!("expected groups, got [{result:?}]");
+    };
+    let expected = HashMap::from([("a".into(), 2), ("b".into(), 1), ("c".into(), 3)]);
+    assert_eq!(groups, expected);
+
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(!comm.is_loading());
+    assert!(comm.data.is_empty());
+}
+
+#[tokio::test]
+async fn group_count_should_neither_prune_nor_be_negated() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    let query = QueryType::group_count(|value: &TestStruct| value.val.clone());
+    assert_eq!(comm.prune_to_query(&query), 0);
+    assert_eq!(comm.data.len(), 4);
+
+    let result = drive(&mut container, comm.query(QueryType::Not(Box::new(query)))).await;
+    assert!(matches!(result.unwrap(), QueryResult::Error(QueryError::Unsupported(_))));
+}
+
+#[tokio::test]
+async fn is_sorted_by_should_detect_violated_order() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert!(comm.data.is_sorted_by(|a: &TestStruct, b| a.key.cmp(&b.key)));
+    assert!(!comm.data.is_sorted_by(|a: &TestStruct, b| b.key.cmp(&a.key)));
+}
+
+#[tokio::test]
+async fn rekey_should_move_value_to_new_key_in_every_communicator() {
+    let mut container = Cont::init(()).await;
+    let [mut all, mut by_id] = container.communicators();
+    let _ = drive(&mut container, all.query(QueryType::All)).await;
+    let _ = drive(&mut container, all.insert_many(n_objects(2, "test"))).await;
+    let _ = drive(&mut container, by_id.query(QueryType::GetById(1))).await;
+    settle(&mut container).await;
+    by_id.state_update();
+    assert_eq!(by_id.data.keys_cloned(), vec![1]);
+
+    let result = drive(&mut container, by_id.rekey(1, 10)).await.unwrap();
+    assert!(result.into_result().is_ok());
+    settle(&mut container).await;
+    all.state_update();
+    by_id.state_update();
+
+    assert!(!container.storage().contains_key(&1));
+    assert_eq!(container.storage().get(&10).unwrap().key, 10);
+    assert_eq!(all.data.keys_cloned().into_iter().sorted().collect_vec(), vec![0, 10]);
+    assert_eq!(by_id.data.keys_cloned(), vec![10]);
+
+    let results = drive(&mut container, all.map_keys([(0, 10)]).join()).await;
+    assert!(matches!(results[..], [ChangeResult::Error(ChangeError::DatabaseError(_))]));
+}
+
+#[tokio::test]
+async fn sorted_keys_should_follow_sort_order() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| b.key.cmp(&a.key));
+
+    let sorted_keys = comm.sorted_keys();
+    assert_eq!(sorted_keys, vec![3, 2, 1, 0]);
+    assert_eq!(
+        sorted_keys,
+        comm.data.sorted().into_iter().map(|value| value.key).collect_vec()
+    );
+    assert!(comm.sorted_diff(&sorted_keys).is_empty());
+}
+
+#[tokio::test]
+async fn state_update_or_wait_should_wake_on_new_change() {
+    let mut container = Cont::init(()).await;
+    let comm = container.communicator();
+
+    let idle_start = Instant::now();
+    container.state_update_or_wait(Duration::from_millis(20)).await;
+    assert!(idle_start.elapsed() >= Duration::from_millis(20));
+
+    let insert = tokio::spawn(comm.insert(TestStruct::new(0, "test")));
+    let start = Instant::now();
+    container.state_update_or_wait(Duration::from_secs(10)).await;
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    settle(&mut container).await;
+    assert!(insert.await.unwrap().unwrap().into_result().is_ok());
+    assert!(container.storage().contains_key(&0));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn state_update_or_wait_should_let_running_work_progress() {
+    let mut container = SlowCont::init(Duration::from_millis(20)).await;
+    let comm = container.communicator();
+
+    let insert = tokio::spawn(comm.insert(TestStruct::new(0, "test")));
+    let start = Instant::now();
+    while !insert.is_finished() {
+        container.state_update_or_wait(Duration::from_millis(100)).await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+    assert!(insert.await.unwrap().unwrap().into_result().is_ok());
+    assert!(container.storage().values.contains_key(&0));
+}
+
+#[tokio::test]
+async fn query_where_should_query_with_inline_closure() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(6, "test"))).await;
+
+    let result = drive(&mut container, comm.query_where(|value: &TestStruct| value.key < 3)).await;
+    assert!(result.unwrap().into_result().is_ok());
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.sorted_keys(), vec![0, 1, 2]);
+}
+
+#[test]
+fn map_values_should_transform_values_and_keep_deleted_keys() {
+    let insert = DataChange::Insert(n_objects(3, "test")).map_values(|value| value.key);
+    assert!(matches!(insert, DataChange::Insert(ref keys) if keys == &vec![0, 1, 2]));
+
+    let delete = DataChange::<usize, TestStruct>::Delete(vec![4, 5]).map_values(|value| value.key);
+    assert!(matches!(delete, DataChange::Delete(ref keys) if keys == &vec![4, 5]));
+}
+
+#[tokio::test]
+async fn await_key_should_resolve_once_value_arrives() {
+    let mut container = Cont::init(()).await;
+    let [inserting, mut waiting] = container.communicators();
+    let _ = drive(&mut container, waiting.query(QueryType::All)).await;
+    settle(&mut container).await;
+    waiting.state_update();
+
+    let awaited = tokio::spawn(async move { waiting.await_key(0).await });
+    let _ = drive(&mut container, inserting.insert(TestStruct::new(0, "test"))).await;
+    let value = drive(&mut container, awaited).await.unwrap();
+    assert_eq!(value, TestStruct::new(0, "test"));
+}
+
+#[tokio::test]
+async fn latest_should_return_greatest_values_up_to_limit() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(6, "test"))).await;
+
+    let by = SortKey::new("key", |a: &TestStruct, b: &TestStruct| a.key.cmp(&b.key));
+    let result = drive(&mut container, comm.query(QueryType::Latest { by, limit: 3 })).await;
+    assert!(matches!(result.unwrap(), QueryResult::Page { total: Some(6) }));
+    settle(&mut container).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| b.key.cmp(&a.key));
+    assert_eq!(comm.sorted_keys(), vec![5, 4, 3]);
+}
+
+#[tokio::test]
+async fn has_key_changed_should_only_report_changed_key() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.set_viewed();
+
+    let _ = drive(&mut container, comm.update(TestStruct::new(1, "changed"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert!(comm.has_key_changed(&1));
+    assert!(!comm.has_key_changed(&0));
+    assert!(!comm.has_key_changed(&2));
+}
+
+#[tokio::test]
+async fn force_broadcast_should_sync_communicators_after_direct_edit() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    let edited = TestStruct::new(1, "edited");
+    let added = TestStruct::new(2, "added");
+    container.storage_mut().insert(1, edited.clone());
+    container.storage_mut().insert(2, added.clone());
+    container.force_broadcast(DataChange::Update(vec![edited.clone()]));
+    container.force_broadcast(DataChange::Insert(vec![added.clone()]));
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert_eq!(comm.data.get(&1), Some(&edited));
+    assert_eq!(comm.data.get(&2), Some(&added));
+    assert_eq!(comm.data.len(), 3);
+}
+
+#[tokio::test]
+async fn page_sorted_by_should_leave_stored_sort_untouched() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    comm.sort(|a: &TestStruct, b: &TestStruct| a.key.cmp(&b.key));
+
+    let descending = |a: &TestStruct, b: &TestStruct| b.key.cmp(&a.key);
+    let page = comm.page_sorted_by(descending, 1, 2).unwrap();
+    assert_eq!(page.iter().map(|value| value.key).collect_vec(), vec![2, 1]);
+    assert!(comm.page_sorted_by(descending, 3, 2).is_none());
+    assert_eq!(comm.sorted_keys(), vec![0, 1, 2, 3, 4]);
+}
+
+#[tokio::test]
+async fn delete_should_cascade_to_children_in_communicators() {
+    let children = HashMap::from([(1, vec![3, 4])]);
+    let mut container = CascadingCont::init(children).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(5, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.len(), 5);
+
+    let result = drive(&mut container, comm.delete(1)).await.unwrap();
+    assert!(result.into_result().is_ok());
+    settle(&mut container).await;
+    comm.state_update();
+
+    assert_eq!(comm.sorted_keys(), vec![0, 2]);
+    assert_eq!(container.storage().values.keys().sorted().collect_vec(), vec![&0, &2]);
+}
+
+#[tokio::test]
+async fn deleted_key_should_be_removed_from_selection() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    comm.select(3).select(1).select(2).deselect(&2);
+    assert!(comm.toggle_select(0));
+    assert!(!comm.toggle_select(0));
+    assert_eq!(comm.selected(), &HashSet::from([1, 3]));
+
+    let _ = drive(&mut container, comm.delete(1)).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.selected(), &HashSet::from([3]));
+    assert_eq!(comm.selected_values(), vec![&TestStruct::new(3, "test")]);
+}
+
+#[tokio::test]
+async fn query_debounced_should_only_send_last_query() {
+    let calls = Calls::default();
+    let mut container = RecordingCont::init(calls.clone()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+
+    for key in 0..3 {
+        comm.query_debounced(QueryType::GetById(key), Duration::from_millis(50));
+        comm.state_update();
+    }
+    settle(&mut container).await;
+    assert_eq!(count_calls(&calls, "get_by_id"), 0);
+    assert!(comm.debounced_result().is_none());
+
+    sleep(Duration::from_millis(50)).await;
+    comm.state_update();
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(count_calls(&calls, "get_by_id"), 1);
+    assert_eq!(comm.sorted_keys(), vec![2]);
+    assert!(matches!(comm.debounced_result(), Some(Ok(QueryResult::Success))));
+}
+
+#[tokio::test]
+async fn query_debounced_should_supersede_running_query() {
+    let mut container = SlowCont::init(Duration::from_millis(50)).await;
+    let mut comm = container.communicator();
+
+    comm.query_debounced(QueryType::GetById(7), Duration::ZERO);
+    comm.state_update();
+    container.state_update();
+    comm.query_debounced(QueryType::All, Duration::ZERO);
+    comm.state_update();
+
+    while comm.debounced_result().is_none() {
+        container.state_update();
+        comm.state_update();
+        sleep(Duration::from_millis(1)).await;
+    }
+    assert!(matches!(comm.debounced_result(), Some(Ok(QueryResult::Success))));
+}
+
+#[tokio::test]
+async fn latest_should_be_repeated_after_insert() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    let by = SortKey::new("key", |a: &TestStruct, b: &TestStruct| a.key.cmp(&b.key));
+    let _ = drive(&mut container, comm.query(QueryType::Latest { by, limit: 2 })).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.keys_iter().copied().sorted().collect_vec(), vec![2, 3]);
+
+    let _ = drive(&mut container, comm.insert(TestStruct::new(9, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+    assert_eq!(comm.data.keys_iter().copied().sorted().collect_vec(), vec![3, 9]);
+}
+
+#[tokio::test]
+async fn latest_should_neither_prune_nor_be_negated() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(4, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    let by = SortKey::new("key", |a: &TestStruct, b: &TestStruct| a.key.cmp(&b.key));
+    let latest = QueryType::Latest { by, limit: 2 };
+    assert_eq!(comm.prune_to_query(&latest), 0);
+    assert_eq!(comm.data.len(), 4);
+
+    let result = drive(&mut container, comm.query(QueryType::Not(Box::new(latest)))).await;
+    assert!(matches!(result.unwrap(), QueryResult::Error(QueryError::Unsupported(_))));
+}
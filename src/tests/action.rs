@@ -1,18 +1,28 @@
+use std::collections::HashMap;
+
 use futures::future::BoxFuture;
 
-use super::{communicators::Communicators, Comm};
+use crate::container::storage::Storage;
+
+use super::{communicators::Communicators, lib_impls::TestStruct, Comm};
 
-pub(super) enum Action {
+pub(super) enum Action<Writer = HashMap<usize, TestStruct>>
+where
+    Writer: Storage<usize, TestStruct>,
+{
     Action(ReadyAction),
-    Assert(AssertAction),
+    Assert(AssertAction<Writer>),
     End,
 }
 
-pub(super) struct AssertAction {
-    pub(super) assert: AssertFn,
+pub(super) struct AssertAction<Writer = HashMap<usize, TestStruct>>
+where
+    Writer: Storage<usize, TestStruct>,
+{
+    pub(super) assert: AssertFn<Writer>,
 }
 
-type AssertFn = Box<dyn FnOnce(&Communicators)>;
+type AssertFn<Writer> = Box<dyn FnOnce(&Communicators<Writer>)>;
 
 pub(super) struct ReadyAction {
     pub(super) which: usize,
@@ -3,20 +3,26 @@ use std::collections::HashMap;
 use futures::future::BoxFuture;
 use itertools::Itertools;
 
-use crate::container::DataContainer;
+use crate::container::{storage::Storage, DataContainer};
 
 use super::{
-    action::{self, Action, AssertAction, ReadyAction}, lib_impls::TestStruct, Comm, Cont
+    action::{self, Action, AssertAction, ReadyAction}, lib_impls::TestStruct, Comm
 };
 
-pub(super) struct Communicators {
-    pub(super) container: Cont,
+pub(super) struct Communicators<Writer = HashMap<usize, TestStruct>>
+where
+    Writer: Storage<usize, TestStruct>,
+{
+    pub(super) container: DataContainer<usize, TestStruct, Writer>,
     pub(super) communicators: HashMap<usize, Comm>,
 }
 
-impl Communicators {
-    pub async fn init(num: usize) -> Self {
-        let mut container = DataContainer::init(()).await;
+impl<Writer> Communicators<Writer>
+where
+    Writer: Storage<usize, TestStruct>,
+{
+    pub async fn init(num: usize, args: Writer::InitArgs) -> Self {
+        let mut container = DataContainer::init(args).await;
         let map = (1..=num)
             .map(|i| (i, container.communicator()))
             .collect::<HashMap<_, _>>();
@@ -37,7 +43,10 @@ impl Communicators {
             .for_each(|comm| comm.state_update());
     }
 
-    pub fn perform_action(&mut self, action: Action) -> Option<BoxFuture<'static, (usize, Comm)>> {
+    pub fn perform_action(
+        &mut self,
+        action: Action<Writer>,
+    ) -> Option<BoxFuture<'static, (usize, Comm)>> {
         match action {
             Action::Action(ready_action) => Some(self.perform_ready_action(ready_action)),
             Action::Assert(assert_action) => {
@@ -59,7 +68,7 @@ impl Communicators {
         })
     }
 
-    pub fn perform_assert(&self, action: AssertAction) {
+    pub fn perform_assert(&self, action: AssertAction<Writer>) {
         (action.assert)(self);
     }
 
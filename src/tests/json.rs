@@ -0,0 +1,33 @@
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::query::QueryType;
+
+use super::{drive, n_objects, settle, Cont, TestStruct};
+
+impl Serialize for TestStruct {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serializer.serialize_struct("TestStruct", 4)?;
+        value.serialize_field("key", &self.key)?;
+        value.serialize_field("val", &self.val)?;
+        value.serialize_field("order", &self.order)?;
+        value.serialize_field("version", &self.version)?;
+        value.end()
+    }
+}
+
+#[tokio::test]
+async fn data_json_should_contain_all_values() {
+    let mut container = Cont::init(()).await;
+    let mut comm = container.communicator();
+    assert_eq!(comm.data_json(), "{}");
+
+    let _ = drive(&mut container, comm.query(QueryType::All)).await;
+    let _ = drive(&mut container, comm.insert_many(n_objects(2, "test"))).await;
+    settle(&mut container).await;
+    comm.state_update();
+
+    let json = comm.data_json();
+    assert!(json.starts_with('{') && json.ends_with('}'));
+    assert!(json.contains(r#""0":{"key":0,"val":"test","order":0,"version":0}"#));
+    assert!(json.contains(r#""1":{"key":1,"val":"test","order":1024,"version":0}"#));
+}
@@ -1,11 +1,16 @@
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use itertools::Itertools;
+use futures::{future::Either, FutureExt};
+use tokio::{sync::mpsc, time::sleep};
 
 use crate::{
-    change::ChangeResult, container::
-        storage::{Future, InitFuture, Storage},
-     query::{Predicate, QueryError, QueryResponse}, GetKey
+    change::{ChangeError, ChangeResult, ChangeType, DataChange, Modifier}, container::
+        storage::{Future, InitError, InitFuture, Storage},
+     query::{FreshData, QueryError, QueryResponse, QueryType, Since}, GetKey, SetKey, Versioned
 };
 
 impl GetKey<usize> for TestStruct {
@@ -14,15 +19,277 @@ impl GetKey<usize> for TestStruct {
     }
 }
 
+impl SetKey<usize> for TestStruct {
+    fn set_key(&mut self, key: usize) {
+        self.key = key;
+    }
+}
+
+impl Versioned for TestStruct {
+    type Cursor = u64;
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+/// Space left between the initial `order` of two neighbouring values so that
+/// moves have room to place values in between.
+const ORDER_GAP: i64 = 1024;
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub(super) struct TestStruct {
     pub(super) key: usize,
     pub(super) val: String,
+    pub(super) order: i64,
+    pub(super) version: u64,
 }
 
 impl TestStruct {
     pub(super) fn new(key: usize, val: &str) -> Self {
-        Self { key, val: val.into() }
+        Self {
+            key,
+            val: val.into(),
+            order: key as i64 * ORDER_GAP,
+            version: 0,
+        }
+    }
+}
+
+/// Test storage that delegates to the `HashMap` storage and calls its [`Hooks`]
+/// on the way, so that each one only implements what sets it apart.
+pub(super) struct Wrapped<H> {
+    /// Handed out without copying by `get_all`.
+    pub(super) values: Arc<HashMap<usize, TestStruct>>,
+    hooks: H,
+}
+
+impl<H> Wrapped<H>
+where
+    H: Hooks,
+{
+    pub(super) fn values_mut(&mut self) -> &mut HashMap<usize, TestStruct> {
+        Arc::make_mut(&mut self.values)
+    }
+
+    /// Resolves to `value` once the [`delay`][Hooks::delay] passed.
+    fn delayed<T>(&self, value: T) -> impl std::future::Future<Output = T> + Send + 'static
+    where
+        T: Send + 'static,
+    {
+        let delay = self.hooks.delay();
+        async move {
+            if !delay.is_zero() {
+                sleep(delay).await;
+            }
+            value
+        }
+    }
+}
+
+/// The futures of the `HashMap` storage are all ready right away, resolving
+/// them lets the hooks see the values after the change.
+fn resolved<T>(future: impl std::future::Future<Output = T>) -> T {
+    future.now_or_never().expect("the HashMap storage never waits")
+}
+
+fn keys_of(values: &[TestStruct]) -> Vec<usize> {
+    values.iter().map(|value| value.key).collect()
+}
+
+/// What a [`Wrapped`] storage does on top of its values, every hook does
+/// nothing by default.
+pub(super) trait Hooks: Sized + Send + Sync + 'static {
+    type InitArgs;
+
+    fn init(args: Self::InitArgs) -> Self;
+    fn try_init(args: Self::InitArgs) -> Result<Self, InitError> {
+        Ok(Self::init(args))
+    }
+    fn migrate(&mut self) -> Result<(), ChangeError> {
+        Ok(())
+    }
+    /// How long every change and query takes to resolve, like a slow database.
+    fn delay(&self) -> Duration {
+        Duration::ZERO
+    }
+    /// Called with the name of every storage method that reaches the values
+    /// and of the `before_change` and `after_change` hooks.
+    fn called(&mut self, _call: &'static str) {}
+    /// Called after the values with `keys` were inserted or updated.
+    fn written(&mut self, _values: &mut HashMap<usize, TestStruct>, _keys: &[usize]) {}
+    /// See [`Storage::on_delete_cascade`].
+    fn cascade(&self, _values: &HashMap<usize, TestStruct>, _key: &usize) -> Vec<usize> {
+        vec![]
+    }
+    /// Values changed after the `cursor`, `None` searches with the predicate
+    /// of the cursor like [`Storage::get_changed_since`] does by default.
+    fn changed_since(
+        &self,
+        _values: &HashMap<usize, TestStruct>,
+        _cursor: Option<u64>,
+    ) -> Option<Vec<TestStruct>> {
+        None
+    }
+    fn external_change_stream(&mut self) -> Option<mpsc::Receiver<DataChange<usize, TestStruct>>> {
+        None
+    }
+    /// Chunks of a streamed query, `None` sends the whole result at once.
+    fn query_streamed(&mut self) -> Option<mpsc::Receiver<QueryResponse<usize, TestStruct>>> {
+        None
+    }
+}
+
+impl<H> Storage<usize, TestStruct> for Wrapped<H>
+where
+    H: Hooks,
+{
+    type InitArgs = H::InitArgs;
+
+    fn init(args: Self::InitArgs) -> impl InitFuture<Self> {
+        let hooks = H::init(args);
+        async move {
+            Self {
+                values: Arc::default(),
+                hooks,
+            }
+        }
+    }
+
+    fn try_init(args: Self::InitArgs) -> impl InitFuture<Result<Self, InitError>> {
+        let hooks = H::try_init(args);
+        async move {
+            hooks.map(|hooks| Self {
+                values: Arc::default(),
+                hooks,
+            })
+        }
+    }
+
+    fn migrate(&mut self) -> impl Future<Result<(), ChangeError>> {
+        let result = self.hooks.migrate();
+        async move { result }
+    }
+
+    fn insert(&mut self, value: &TestStruct) -> impl Future<ChangeResult> {
+        self.hooks.called("insert");
+        let result = resolved(Storage::insert(self.values_mut(), value));
+        self.hooks.written(Arc::make_mut(&mut self.values), &[value.key]);
+        self.delayed(result)
+    }
+
+    fn insert_many(&mut self, values: &[TestStruct]) -> impl Future<ChangeResult> {
+        self.hooks.called("insert_many");
+        let result = resolved(Storage::insert_many(self.values_mut(), values));
+        self.hooks.written(Arc::make_mut(&mut self.values), &keys_of(values));
+        self.delayed(result)
+    }
+
+    fn update(&mut self, value: &TestStruct) -> impl Future<ChangeResult> {
+        self.hooks.called("update");
+        let result = resolved(Storage::update(self.values_mut(), value));
+        self.hooks.written(Arc::make_mut(&mut self.values), &[value.key]);
+        self.delayed(result)
+    }
+
+    fn update_many(&mut self, values: &[TestStruct]) -> impl Future<ChangeResult> {
+        self.hooks.called("update_many");
+        let result = resolved(Storage::update_many(self.values_mut(), values));
+        self.hooks.written(Arc::make_mut(&mut self.values), &keys_of(values));
+        self.delayed(result)
+    }
+
+    fn delete(&mut self, key: &usize) -> impl Future<ChangeResult> {
+        self.hooks.called("delete");
+        let result = resolved(Storage::delete(self.values_mut(), key));
+        self.delayed(result)
+    }
+
+    fn delete_many(&mut self, keys: &[usize]) -> impl Future<ChangeResult> {
+        self.hooks.called("delete_many");
+        let result = resolved(Storage::delete_many(self.values_mut(), keys));
+        self.delayed(result)
+    }
+
+    fn on_delete_cascade(&mut self, key: &usize) -> impl Future<Vec<usize>> {
+        let cascaded = self.hooks.cascade(&self.values, key);
+        async move { cascaded }
+    }
+
+    fn get_all(&mut self) -> impl Future<QueryResponse<usize, TestStruct>> {
+        self.hooks.called("get_all");
+        self.delayed(QueryResponse::Ok(self.values.clone().into()))
+    }
+
+    fn get_by_id(&mut self, key: usize) -> impl Future<QueryResponse<usize, TestStruct>> {
+        self.hooks.called("get_by_id");
+        let response = resolved(Storage::get_by_id(self.values_mut(), key));
+        self.delayed(response)
+    }
+
+    fn get_by_ids(&mut self, keys: Vec<usize>) -> impl Future<QueryResponse<usize, TestStruct>> {
+        self.hooks.called("get_by_ids");
+        let response = resolved(Storage::get_by_ids(self.values_mut(), keys));
+        self.delayed(response)
+    }
+
+    fn get_changed_since(
+        &mut self,
+        since: Since<TestStruct>,
+    ) -> impl Future<QueryResponse<usize, TestStruct>> {
+        let cursor = since.cursor::<u64>().copied();
+        match self.hooks.changed_since(&self.values, cursor) {
+            Some(changed) => Either::Left(async move { QueryResponse::Ok(changed.into()) }),
+            None => Either::Right(self.get_by_predicate(since.predicate())),
+        }
+    }
+
+    fn before_change(&mut self, _change: &ChangeType<usize, TestStruct>) {
+        self.hooks.called("before_change");
+    }
+
+    fn after_change(&mut self, _result: &ChangeResult) {
+        self.hooks.called("after_change");
+    }
+
+    fn external_change_stream(
+        &mut self,
+    ) -> Option<mpsc::Receiver<DataChange<usize, TestStruct>>> {
+        self.hooks.external_change_stream()
+    }
+
+    fn query_streamed(
+        &mut self,
+        query: QueryType<usize, TestStruct>,
+    ) -> mpsc::Receiver<QueryResponse<usize, TestStruct>> {
+        match self.hooks.query_streamed() {
+            Some(reciver) => reciver,
+            None => Storage::query_streamed(self.values_mut(), query),
+        }
+    }
+}
+
+/// Names of the storage methods a [`RecordingStorage`] was called with, in order.
+pub(super) type Calls = Arc<Mutex<Vec<&'static str>>>;
+
+pub(super) fn count_calls(calls: &Calls, call: &str) -> usize {
+    calls.lock().unwrap().iter().filter(|c| **c == call).count()
+}
+
+/// Behaves exactly like the `HashMap` storage but records every call made to
+/// it, to check which calls actually reach the storage.
+pub(super) type RecordingStorage = Wrapped<RecordCalls>;
+
+pub(super) struct RecordCalls(Calls);
+
+impl Hooks for RecordCalls {
+    type InitArgs = Calls;
+
+    fn init(calls: Self::InitArgs) -> Self {
+        Self(calls)
+    }
+
+    fn called(&mut self, call: &'static str) {
+        self.0.lock().unwrap().push(call);
     }
 }
 
@@ -60,15 +327,88 @@ impl Storage<usize, TestStruct> for HashMap<usize, TestStruct> {
     }
 
     fn delete(&mut self, key: &usize) -> impl Future<ChangeResult> {
-        self.remove(key);
-        async move { ChangeResult::Success }
+        let affected = usize::from(self.remove(key).is_some());
+        async move { ChangeResult::Affected(affected) }
     }
 
     fn delete_many(&mut self, keys: &[usize]) -> impl Future<ChangeResult> {
-        for key in keys {
-            self.remove(key);
+        let affected = keys.iter().filter(|key| self.remove(key).is_some()).count();
+        async move { ChangeResult::Affected(affected) }
+    }
+
+    fn reorder(
+        &mut self,
+        key: &usize,
+        before: Option<&usize>,
+        after: Option<&usize>,
+    ) -> impl Future<Result<TestStruct, ChangeError>> {
+        let order_of = |key: Option<&usize>| key.and_then(|key| self.get(key)).map(|val| val.order);
+        let new_order = match (order_of(after), order_of(before)) {
+            (Some(low), Some(high)) => Some(low + (high - low) / 2),
+            (Some(low), None) => Some(low + ORDER_GAP),
+            (None, Some(high)) => Some(high - ORDER_GAP),
+            (None, None) => None,
+        };
+        let res = match self.get_mut(key) {
+            Some(val) => {
+                if let Some(order) = new_order {
+                    val.order = order;
+                }
+                Ok(val.clone())
+            }
+            None => Err(ChangeError::DatabaseError(format!("No value with key [{key}]"))),
+        };
+        async move { res }
+    }
+
+    fn modify(
+        &mut self,
+        key: &usize,
+        f: Modifier<TestStruct>,
+    ) -> impl Future<Result<TestStruct, ChangeError>> {
+        let res = match self.get_mut(key) {
+            Some(val) => {
+                f(val);
+                Ok(val.clone())
+            }
+            None => Err(ChangeError::DatabaseError(format!("No value with key [{key}]"))),
+        };
+        async move { res }
+    }
+
+    fn rekey(
+        &mut self,
+        old: &usize,
+        new: &usize,
+        set_key: Modifier<TestStruct>,
+    ) -> impl Future<Result<TestStruct, ChangeError>> {
+        let res = if self.contains_key(new) {
+            Err(ChangeError::DatabaseError(format!("Key [{new}] is already taken")))
+        } else {
+            match self.remove(old) {
+                Some(mut val) => {
+                    set_key(&mut val);
+                    self.insert(*new, val.clone());
+                    Ok(val)
+                }
+                None => Err(ChangeError::DatabaseError(format!("No value with key [{old}]"))),
+            }
+        };
+        async move { res }
+    }
+
+    fn insert_if_absent_many(
+        &mut self,
+        values: &[TestStruct],
+    ) -> impl Future<Result<Vec<TestStruct>, ChangeError>> {
+        let mut inserted = vec![];
+        for value in values {
+            if let Entry::Vacant(entry) = self.entry(value.key) {
+                entry.insert(value.clone());
+                inserted.push(value.clone());
+            }
         }
-        async move { ChangeResult::Success }
+        async move { Ok(inserted) }
     }
 
     fn get_all(&mut self) -> impl Future<QueryResponse<usize, TestStruct>> {
@@ -95,18 +435,213 @@ impl Storage<usize, TestStruct> for HashMap<usize, TestStruct> {
                 break;
             }
         }
-        async move { err.unwrap_or(QueryResponse::Ok(vals.into())) }
+        let fresh_data = FreshData::from(vals).with_total(self.len());
+        async move { err.unwrap_or(QueryResponse::Ok(fresh_data)) }
     }
+}
 
-    fn get_by_predicate(
-        &mut self,
-        predicate: Predicate<TestStruct>,
-    ) -> impl Future<QueryResponse<usize, TestStruct>> {
-        let values = self
-            .values()
-            .filter(|val| predicate(val))
-            .cloned()
-            .collect_vec();
-        async move { QueryResponse::Ok(values.into()) }
+/// Behaves like the `HashMap` storage but stamps every written value with a
+/// monotonically increasing version.
+pub(super) type VersionedStorage = Wrapped<StampVersions>;
+
+pub(super) struct StampVersions {
+    version: u64,
+}
+
+impl Hooks for StampVersions {
+    type InitArgs = ();
+
+    fn init(_: Self::InitArgs) -> Self {
+        Self { version: 0 }
+    }
+
+    fn written(&mut self, values: &mut HashMap<usize, TestStruct>, keys: &[usize]) {
+        for key in keys {
+            if let Some(value) = values.get_mut(key) {
+                self.version += 1;
+                value.version = self.version;
+            }
+        }
+    }
+
+    fn changed_since(
+        &self,
+        values: &HashMap<usize, TestStruct>,
+        cursor: Option<u64>,
+    ) -> Option<Vec<TestStruct>> {
+        let cursor = cursor.unwrap_or_default();
+        Some(
+            values
+                .values()
+                .filter(|value| value.version > cursor)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// Keeps its values behind an [`Arc`] and hands them out without copying,
+/// which every [`Wrapped`] storage does.
+pub(super) type SharedStorage = Wrapped<()>;
+
+impl Hooks for () {
+    type InitArgs = ();
+
+    fn init(_: Self::InitArgs) -> Self {}
+}
+
+/// Sender side of the external change stream of an [`ExternalStorage`], taking
+/// it out simulates a dropped connection.
+pub(super) type ExternalFeed = Arc<Mutex<Option<mpsc::Sender<DataChange<usize, TestStruct>>>>>;
+
+/// Storage with a stream of external changes that is fed through the
+/// [`ExternalFeed`].
+pub(super) type ExternalStorage = Wrapped<FeedExternal>;
+
+pub(super) struct FeedExternal(ExternalFeed);
+
+impl Hooks for FeedExternal {
+    type InitArgs = ExternalFeed;
+
+    fn init(feed: Self::InitArgs) -> Self {
+        Self(feed)
+    }
+
+    fn external_change_stream(&mut self) -> Option<mpsc::Receiver<DataChange<usize, TestStruct>>> {
+        let (sender, reciver) = mpsc::channel(10);
+        *self.0.lock().unwrap() = Some(sender);
+        Some(reciver)
+    }
+}
+
+/// How many more times a [`FlakyStorage`] fails to initialize.
+pub(super) type RemainingFailures = Arc<Mutex<usize>>;
+
+/// Storage that fails [`Storage::try_init`] until the [`RemainingFailures`]
+/// are used up, like a database that isn't up yet.
+pub(super) type FlakyStorage = Wrapped<FailInit>;
+
+pub(super) struct FailInit;
+
+impl Hooks for FailInit {
+    type InitArgs = RemainingFailures;
+
+    fn init(_: Self::InitArgs) -> Self {
+        Self
+    }
+
+    fn try_init(failures: Self::InitArgs) -> Result<Self, InitError> {
+        let mut failures = failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Err(InitError(String::from("connection refused")));
+        }
+        Ok(Self)
+    }
+}
+
+/// Sender side of the chunks of the last streamed query of a
+/// [`StreamingStorage`], dropping it ends the query.
+pub(super) type ChunkFeed = Arc<Mutex<Option<mpsc::Sender<QueryResponse<usize, TestStruct>>>>>;
+
+/// Storage whose streamed queries return the chunks fed through the
+/// [`ChunkFeed`].
+pub(super) type StreamingStorage = Wrapped<FeedChunks>;
+
+pub(super) struct FeedChunks(ChunkFeed);
+
+impl Hooks for FeedChunks {
+    type InitArgs = ChunkFeed;
+
+    fn init(feed: Self::InitArgs) -> Self {
+        Self(feed)
+    }
+
+    fn query_streamed(&mut self) -> Option<mpsc::Receiver<QueryResponse<usize, TestStruct>>> {
+        let (sender, reciver) = mpsc::channel(10);
+        *self.0.lock().unwrap() = Some(sender);
+        Some(reciver)
+    }
+}
+
+/// How many times a [`MigratingStorage`] was migrated.
+pub(super) type Migrations = Arc<Mutex<usize>>;
+
+/// Storage that counts how often [`Storage::migrate`] is called.
+pub(super) type MigratingStorage = Wrapped<CountMigrations>;
+
+pub(super) struct CountMigrations(Migrations);
+
+impl Hooks for CountMigrations {
+    type InitArgs = Migrations;
+
+    fn init(migrations: Self::InitArgs) -> Self {
+        Self(migrations)
+    }
+
+    fn migrate(&mut self) -> Result<(), ChangeError> {
+        *self.0.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+/// Storage whose [`Storage::migrate`] always fails.
+pub(super) type UnmigratableStorage = Wrapped<FailMigration>;
+
+pub(super) struct FailMigration;
+
+impl Hooks for FailMigration {
+    type InitArgs = ();
+
+    fn init(_: Self::InitArgs) -> Self {
+        Self
+    }
+
+    fn migrate(&mut self) -> Result<(), ChangeError> {
+        Err(ChangeError::DatabaseError("migration failed".into()))
+    }
+}
+
+/// Keys of the children of each parent key in a [`CascadingStorage`].
+pub(super) type Children = HashMap<usize, Vec<usize>>;
+
+/// Storage that reports the [`Children`] of a value as cascading from it, like
+/// a database with an `ON DELETE CASCADE`.
+pub(super) type CascadingStorage = Wrapped<CascadeDeletes>;
+
+pub(super) struct CascadeDeletes(Children);
+
+impl Hooks for CascadeDeletes {
+    type InitArgs = Children;
+
+    fn init(children: Self::InitArgs) -> Self {
+        Self(children)
+    }
+
+    fn cascade(&self, values: &HashMap<usize, TestStruct>, key: &usize) -> Vec<usize> {
+        self.0
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter(|child| values.contains_key(child))
+            .copied()
+            .collect()
+    }
+}
+
+/// Storage that takes the given time to resolve every change and query.
+pub(super) type SlowStorage = Wrapped<Delay>;
+
+pub(super) struct Delay(Duration);
+
+impl Hooks for Delay {
+    type InitArgs = Duration;
+
+    fn init(delay: Self::InitArgs) -> Self {
+        Self(delay)
+    }
+
+    fn delay(&self) -> Duration {
+        self.0
     }
 }
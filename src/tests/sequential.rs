@@ -1,31 +1,46 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use tokio::{sync::mpsc, time::sleep};
 
-use super::{action::Action, communicators::Communicators};
+use crate::container::storage::Storage;
 
-#[derive(Default)]
-pub(super) struct SequentialBuilder {
+use super::{action::Action, communicators::Communicators, lib_impls::TestStruct};
+
+pub(super) struct SequentialBuilder<Writer = HashMap<usize, TestStruct>>
+where
+    Writer: Storage<usize, TestStruct>,
+{
     num_communicators: usize,
-    actions: Vec<Action>,
+    actions: Vec<Action<Writer>>,
 }
 
-impl SequentialBuilder {
+impl<Writer> SequentialBuilder<Writer>
+where
+    Writer: Storage<usize, TestStruct>,
+{
     pub(super) fn new(num_communicators: usize) -> Self {
         Self {
             num_communicators,
-            ..Default::default()
+            actions: vec![],
         }
     }
-    pub(super) fn actions(mut self, actions: Vec<Action>) -> Self {
+    pub(super) fn actions(mut self, actions: Vec<Action<Writer>>) -> Self {
         self.actions = actions;
         if let Some(Action::Action(_)) | Some(Action::Assert(_)) = self.actions.last() {
             self.actions.push(Action::End);
         };
         self
     }
-    pub(super) async fn run(mut self) -> Communicators {
-        let mut all = Communicators::init(self.num_communicators).await;
+    pub(super) async fn run(self) -> Communicators<Writer>
+    where
+        Writer: Storage<usize, TestStruct, InitArgs = ()>,
+    {
+        self.run_with(()).await
+    }
+    /// Same as [`run`][SequentialBuilder::run] but for storages that need
+    /// arguments to be initialized.
+    pub(super) async fn run_with(mut self, args: Writer::InitArgs) -> Communicators<Writer> {
+        let mut all = Communicators::init(self.num_communicators, args).await;
 
         let (action_sender, mut action_reciver) = mpsc::channel(5);
         let (result_sender, mut result_reciver) = mpsc::channel(5);
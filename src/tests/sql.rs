@@ -0,0 +1,107 @@
+use sqlx::{sqlite::SqlitePoolOptions, FromRow, Row, Sqlite, SqlitePool};
+
+use crate::{
+    change::ChangeResult,
+    container::{
+        storage::{
+            sql::{SqlBuilder, SqlxStorage, SqlxTable},
+            Storage,
+        },
+        DataContainer,
+    },
+    query::{QueryResponse, QueryType},
+};
+
+use super::{drive, n_objects, TestStruct};
+
+type SqlCont = DataContainer<usize, TestStruct, SqlxStorage<usize, TestStruct, Sqlite>>;
+
+impl FromRow<'_, sqlx::sqlite::SqliteRow> for TestStruct {
+    fn from_row(row: &sqlx::sqlite::SqliteRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            key: row.try_get::<i64, _>("key")? as usize,
+            val: row.try_get("val")?,
+            order: row.try_get("sort_order")?,
+            version: row.try_get::<i64, _>("version")? as u64,
+        })
+    }
+}
+
+impl SqlxTable<usize, Sqlite> for TestStruct {
+    const TABLE: &'static str = "test_structs";
+    const KEY_COLUMN: &'static str = "key";
+    const COLUMNS: &'static [&'static str] = &["key", "val", "sort_order", "version"];
+
+    fn push_key<'args>(key: &usize, query: &mut SqlBuilder<'args, Sqlite>) {
+        query.push_bind(*key as i64);
+    }
+
+    fn push_column<'args>(&self, column: &str, query: &mut SqlBuilder<'args, Sqlite>) {
+        match column {
+            "key" => query.push_bind(self.key as i64),
+            "val" => query.push_bind(self.val.clone()),
+            "sort_order" => query.push_bind(self.order),
+            "version" => query.push_bind(self.version as i64),
+            _ => unreachable!("Unknown column [{column}]"),
+        };
+    }
+}
+
+async fn in_memory_pool() -> SqlitePool {
+    // every connection would open its own in memory database
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::query(
+        "CREATE TABLE test_structs (
+            key INTEGER PRIMARY KEY,
+            val TEXT NOT NULL,
+            sort_order INTEGER NOT NULL,
+            version INTEGER NOT NULL
+        )",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    pool
+}
+
+#[tokio::test]
+async fn sqlx_storage_should_write_and_read_from_sqlite() {
+    let mut container = SqlCont::init(in_memory_pool().await).await;
+    let comm = container.communicator();
+
+    let _ = drive(&mut container, comm.insert_many(n_objects(3, "test"))).await;
+    let _ = drive(&mut container, comm.update(TestStruct::new(1, "changed"))).await;
+    let _ = drive(&mut container, comm.delete(2)).await;
+
+    let mut values = drive(&mut container, comm.fetch(QueryType::All))
+        .await
+        .unwrap();
+    values.sort_by_key(|value| value.key);
+    assert_eq!(
+        values,
+        vec![TestStruct::new(0, "test"), TestStruct::new(1, "changed")]
+    );
+
+    let by_ids = drive(&mut container, comm.fetch(QueryType::GetByIds(vec![0, 2])))
+        .await
+        .unwrap();
+    assert_eq!(by_ids, vec![TestStruct::new(0, "test")]);
+    assert!(drive(&mut container, comm.fetch(QueryType::GetById(2)))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn sqlx_storage_should_skip_statements_without_keys() {
+    let mut storage = SqlxStorage::<usize, TestStruct, Sqlite>::init(in_memory_pool().await).await;
+    assert!(matches!(storage.delete_many(&[]).await, ChangeResult::Success));
+    assert!(matches!(storage.insert_many(&[]).await, ChangeResult::Success));
+    assert!(matches!(
+        storage.get_by_ids(vec![]).await,
+        QueryResponse::Ok(values) if values.is_empty()
+    ));
+}
@@ -43,7 +43,11 @@ impl<T> DrainIf<T> for Vec<T> {
         indexes.sort();
         indexes.reverse();
 
-        indexes.into_iter().map(|index| self.remove(index))
+        // removed back to front so the indexes stay valid, but returned in
+        // their original order
+        let mut removed = indexes.into_iter().map(|index| self.remove(index)).collect_vec();
+        removed.reverse();
+        removed.into_iter()
     }
     fn drain_if<F: FnMut(&mut T) -> bool>(&mut self, pred: F) -> Vec<T> {
         self.drain_if_iter(pred).collect_vec()